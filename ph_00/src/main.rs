@@ -1,8 +1,8 @@
-use std::thread;
-use std::{net::{TcpListener, TcpStream, Shutdown}};
-use std::io::{Read, Write};
 use clap::Parser;
+use ph_common::limits::{LimitArgs, Limits};
+use std::error::Error;
 
+use protohackers::server::{EchoServer, Mode, DEFAULT_BUFFER_SIZE};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -14,47 +14,36 @@ struct Args {
     /// IP to listen on
     #[arg(long, default_value_t = String::from("0.0.0.0"))]
     host: String,
+
+    /// Size of the per-connection read buffer in bytes
+    #[arg(long, default_value_t = DEFAULT_BUFFER_SIZE)]
+    buffer_size: usize,
+
+    /// Service to provide
+    #[arg(long, value_enum, default_value_t = Mode::Echo)]
+    mode: Mode,
+
+    /// Also serve over UDP on the same port
+    #[arg(long)]
+    udp: bool,
+
+    #[command(flatten)]
+    limits: LimitArgs,
 }
 
-fn main() {
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
 
     let hostname = format!("{}:{}", args.host, args.port);
-    println!("Will start listening on {}", hostname);
-
-    let listener = TcpListener::bind(hostname).
-        expect("Unable to bind to socket");
-
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                thread::spawn(|| {
-                    handle_client(stream);
-                });
-            }
-            Err(e) => {
-                println!("Error: {}", e);
-            }
-        }
-    }
-}
+    println!("Will start listening on {hostname}");
+
+    let server = EchoServer::new()
+        .with_buffer_size(args.buffer_size)
+        .with_mode(args.mode)
+        .with_udp(args.udp)
+        .with_limits(Limits::new(args.limits));
+    server.run(hostname).await?;
 
-fn handle_client(mut stream: TcpStream) {
-    let mut buffer = [0; 1024];
-
-    println!("New connection: {}", stream.peer_addr().unwrap());
-    loop {
-        if let Ok(read) = stream.read(&mut buffer) {
-            if read == 0 {
-                break;
-            }
-            if let Err(_) = stream.write(&buffer[0..read]) {
-                stream.shutdown(Shutdown::Both).unwrap();
-                break;
-            }
-        } else {
-            break;
-        }
-    }
-    println!("Disconnected: {}", stream.peer_addr().unwrap())
+    Ok(())
 }
@@ -0,0 +1,283 @@
+use ph_common::limits::{Limits, RateLimiter};
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::IpAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio::net::UdpSocket;
+
+pub const DEFAULT_BUFFER_SIZE: usize = 1024;
+
+/* RFC 864 line: 72 printable characters followed by CRLF */
+pub const CHARGEN_LINE_LEN: usize = 72 + 2;
+const CHARGEN_FIRST: u8 = b' ';
+const CHARGEN_CHARS: usize = 95;
+const CHARGEN_MAX_DATAGRAM: usize = 512;
+/*
+ * UDP chargen answers every datagram, forged source addresses included, with
+ * many times its size, so it is always throttled per source IP, to this many
+ * datagrams per second unless --max-messages-per-sec says otherwise.
+ */
+pub const CHARGEN_UDP_RATE: u32 = 2;
+
+const MAX_DATAGRAM: usize = 65536;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Mode {
+    /// Send back everything received (RFC 862)
+    #[default]
+    Echo,
+    /// Throw away everything received (RFC 863)
+    Discard,
+    /// Send a rotating character pattern (RFC 864)
+    Chargen,
+}
+
+pub fn chargen_line(line: usize) -> [u8; CHARGEN_LINE_LEN] {
+    let mut buf = [0u8; CHARGEN_LINE_LEN];
+
+    for (i, c) in buf[..CHARGEN_LINE_LEN - 2].iter_mut().enumerate() {
+        *c = CHARGEN_FIRST + ((line + i) % CHARGEN_CHARS) as u8;
+    }
+    buf[CHARGEN_LINE_LEN - 2..].copy_from_slice(b"\r\n");
+
+    buf
+}
+
+#[derive(Debug)]
+pub struct EchoServer {
+    buffer_size: usize,
+    mode: Mode,
+    udp: bool,
+    limits: Limits,
+}
+
+impl Default for EchoServer {
+    fn default() -> Self {
+        EchoServer {
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            mode: Mode::default(),
+            udp: false,
+            limits: Limits::default(),
+        }
+    }
+}
+
+impl EchoServer {
+    pub fn new() -> EchoServer {
+        EchoServer::default()
+    }
+
+    pub fn with_buffer_size(mut self, buffer_size: usize) -> EchoServer {
+        self.buffer_size = buffer_size.max(1);
+        self
+    }
+
+    pub fn with_mode(mut self, mode: Mode) -> EchoServer {
+        self.mode = mode;
+        self
+    }
+
+    /* also serve the same mode over UDP on the TCP port */
+    pub fn with_udp(mut self, udp: bool) -> EchoServer {
+        self.udp = udp;
+        self
+    }
+
+    /* cap connections, throttle chatty clients and drop idle ones */
+    pub fn with_limits(mut self, limits: Limits) -> EchoServer {
+        self.limits = limits;
+        self
+    }
+
+    pub async fn run(self, hostname: String) -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind(hostname).await?;
+
+        if self.udp {
+            let socket = UdpSocket::bind(listener.local_addr()?).await?;
+            let mode = self.mode;
+            let limits = self.limits.clone();
+
+            if mode == Mode::Chargen {
+                println!(
+                    "Warning: UDP chargen can be abused to flood forged source addresses, \
+                     replies are rate limited per IP"
+                );
+            }
+
+            tokio::spawn(async move {
+                if let Err(e) = EchoServer::run_udp(socket, mode, limits).await {
+                    println!("UDP error occurred: {e:?}");
+                }
+            });
+        }
+
+        loop {
+            let (stream, addr) = listener.accept().await?;
+            let guard = match self.limits.acquire(addr.ip()) {
+                Some(guard) => guard,
+                None => {
+                    println!(
+                        "Rejected connection from {addr}, {} rejected so far",
+                        self.limits.rejected()
+                    );
+                    continue;
+                }
+            };
+            let limits = self.limits.clone();
+            let buffer_size = self.buffer_size;
+            let mode = self.mode;
+
+            tokio::spawn(async move {
+                let _guard = guard;
+
+                println!("New connection: {addr}");
+                let result = match mode {
+                    Mode::Echo => EchoServer::handle_client(stream, buffer_size, limits).await,
+                    Mode::Discard => EchoServer::handle_discard(stream, buffer_size, limits).await,
+                    Mode::Chargen => EchoServer::handle_chargen(stream, buffer_size).await,
+                };
+                if let Err(e) = result {
+                    println!("Error occurred: {e:?}");
+                }
+                println!("Disconnected: {addr}");
+            });
+        }
+    }
+
+    /*
+     * Echo everything back until the client shuts down its write half, then
+     * close our side. Data still in flight when the client half-closes is
+     * echoed before the shutdown.
+     */
+    pub async fn handle_client(
+        stream: TcpStream,
+        buffer_size: usize,
+        limits: Limits,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut stream = limits.wrap(stream);
+        let mut rate_limiter = limits.rate_limiter();
+        let mut buffer = vec![0; buffer_size];
+
+        loop {
+            let read = stream.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+
+            rate_limiter.acquire().await;
+            stream.write_all(&buffer[..read]).await?;
+        }
+
+        stream.shutdown().await?;
+
+        Ok(())
+    }
+
+    pub async fn handle_discard(
+        stream: TcpStream,
+        buffer_size: usize,
+        limits: Limits,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut stream = limits.wrap(stream);
+        let mut rate_limiter = limits.rate_limiter();
+        let mut buffer = vec![0; buffer_size];
+
+        while stream.read(&mut buffer).await? > 0 {
+            rate_limiter.acquire().await;
+        }
+
+        stream.shutdown().await?;
+
+        Ok(())
+    }
+
+    /*
+     * Keep sending lines until the client goes away. Anything the client
+     * sends is discarded; a half-close stops the reading but not the lines.
+     * Chargen clients usually never send anything, so there is no idle
+     * timeout here.
+     */
+    pub async fn handle_chargen(
+        stream: TcpStream,
+        buffer_size: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let (mut reader, mut writer) = stream.into_split();
+        let mut buffer = vec![0; buffer_size];
+
+        let generate = async {
+            let mut line = 0;
+            while writer.write_all(&chargen_line(line)).await.is_ok() {
+                line += 1;
+            }
+        };
+        let drain = async {
+            while let Ok(read) = reader.read(&mut buffer).await {
+                if read == 0 {
+                    break;
+                }
+            }
+            std::future::pending::<()>().await;
+        };
+
+        tokio::select! {
+            _ = generate => {},
+            _ = drain => {},
+        }
+
+        Ok(())
+    }
+
+    pub async fn run_udp(
+        socket: UdpSocket,
+        mode: Mode,
+        limits: Limits,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut rate_limiters: HashMap<IpAddr, RateLimiter> = HashMap::new();
+        let mut buffer = vec![0; MAX_DATAGRAM];
+        let mut line = 0;
+
+        loop {
+            /* errors here are mostly ICMP unreachable replies to earlier sends */
+            let (len, addr) = match socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(e) => {
+                    println!("UDP receive error: {e:?}");
+                    continue;
+                }
+            };
+
+            if rate_limiters.len() > 1024 {
+                rate_limiters.retain(|_, limiter| !limiter.is_full());
+            }
+            let limiter = rate_limiters
+                .entry(addr.ip())
+                .or_insert_with(|| match mode {
+                    Mode::Chargen => limits.rate_limiter_or(CHARGEN_UDP_RATE),
+                    _ => limits.rate_limiter(),
+                });
+            if !limiter.try_acquire() {
+                println!("Dropping datagram from {addr:?}, rate limited");
+                continue;
+            }
+
+            let reply = match mode {
+                Mode::Echo => buffer[..len].to_vec(),
+                Mode::Discard => continue,
+                Mode::Chargen => {
+                    let mut reply = Vec::with_capacity(CHARGEN_MAX_DATAGRAM);
+                    while reply.len() + CHARGEN_LINE_LEN <= CHARGEN_MAX_DATAGRAM {
+                        reply.extend_from_slice(&chargen_line(line));
+                        line += 1;
+                    }
+                    reply
+                }
+            };
+
+            if let Err(e) = socket.send_to(&reply, addr).await {
+                println!("UDP send error to {addr:?}: {e:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::net::UdpSocket;
+use tokio::time::{sleep, timeout, Duration};
+
+use protohackers::server::{chargen_line, EchoServer, Mode, CHARGEN_LINE_LEN, CHARGEN_UDP_RATE};
+
+async fn spawn_app(port: u16, server: EchoServer) -> String {
+    let hostname = format!("127.0.0.1:{port}");
+    let listen = hostname.clone();
+    tokio::spawn(async move {
+        let _ = server.run(listen).await;
+    });
+    sleep(Duration::from_millis(100)).await;
+
+    hostname
+}
+
+/* writes the payload, half-closes and collects everything echoed back */
+async fn echo(hostname: &str, payload: Vec<u8>) -> Vec<u8> {
+    let stream = TcpStream::connect(hostname).await.unwrap();
+    let (mut reader, mut writer) = stream.into_split();
+
+    let sender = tokio::spawn(async move {
+        writer.write_all(&payload).await.unwrap();
+        writer.shutdown().await.unwrap();
+    });
+
+    let mut received = Vec::new();
+    timeout(Duration::from_secs(10), reader.read_to_end(&mut received))
+        .await
+        .expect("Server did not close the connection")
+        .unwrap();
+    sender.await.unwrap();
+
+    received
+}
+
+#[tokio::test]
+async fn test_echo() {
+    let hostname = spawn_app(7777, EchoServer::new()).await;
+
+    let mut stream = TcpStream::connect(&hostname).await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+
+    let mut buf = [0u8; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[tokio::test]
+async fn test_large_payload() {
+    let hostname = spawn_app(7778, EchoServer::new()).await;
+
+    let payload: Vec<u8> = (0..4 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let received = echo(&hostname, payload.clone()).await;
+
+    assert_eq!(received.len(), payload.len());
+    assert!(received == payload);
+}
+
+#[tokio::test]
+async fn test_half_close() {
+    let hostname = spawn_app(7779, EchoServer::new()).await;
+
+    /* nothing is read until after the shutdown, all of it must still arrive */
+    let mut stream = TcpStream::connect(&hostname).await.unwrap();
+    stream.write_all(b"first ").await.unwrap();
+    stream.write_all(b"second").await.unwrap();
+    stream.shutdown().await.unwrap();
+
+    let mut received = Vec::new();
+    timeout(Duration::from_secs(5), stream.read_to_end(&mut received))
+        .await
+        .expect("Server did not close the connection")
+        .unwrap();
+    assert_eq!(received, b"first second");
+}
+
+#[tokio::test]
+async fn test_empty_session() {
+    let hostname = spawn_app(7780, EchoServer::new()).await;
+
+    let received = echo(&hostname, Vec::new()).await;
+    assert!(received.is_empty());
+}
+
+#[tokio::test]
+async fn test_small_buffer() {
+    let hostname = spawn_app(7781, EchoServer::new().with_buffer_size(3)).await;
+
+    let payload = b"The quick brown fox jumps over the lazy dog".to_vec();
+    let received = echo(&hostname, payload.clone()).await;
+    assert_eq!(received, payload);
+}
+
+#[tokio::test]
+async fn test_discard() {
+    let hostname = spawn_app(7782, EchoServer::new().with_mode(Mode::Discard)).await;
+
+    let received = echo(&hostname, b"nobody will ever see this".to_vec()).await;
+    assert!(received.is_empty());
+}
+
+#[tokio::test]
+async fn test_chargen() {
+    let hostname = spawn_app(7783, EchoServer::new().with_mode(Mode::Chargen)).await;
+
+    /* half-closing does not stop the generator */
+    let mut stream = TcpStream::connect(&hostname).await.unwrap();
+    stream.shutdown().await.unwrap();
+
+    let mut buf = vec![0u8; CHARGEN_LINE_LEN * 100];
+    stream.read_exact(&mut buf).await.unwrap();
+
+    for (i, line) in buf.chunks(CHARGEN_LINE_LEN).enumerate() {
+        assert_eq!(line, chargen_line(i));
+    }
+    assert!(buf.starts_with(b" !\"#$%&"));
+    assert!(buf.ends_with(b"\r\n"));
+    assert!(chargen_line(95) == chargen_line(0));
+}
+
+#[tokio::test]
+async fn test_udp_echo() {
+    let hostname = spawn_app(7784, EchoServer::new().with_udp(true)).await;
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.send_to(b"ping", &hostname).await.unwrap();
+
+    let mut buf = [0u8; 64];
+    let (len, _addr) = timeout(Duration::from_secs(1), socket.recv_from(&mut buf))
+        .await
+        .expect("No UDP reply")
+        .unwrap();
+    assert_eq!(&buf[..len], b"ping");
+
+    /* TCP keeps working alongside */
+    let received = echo(&hostname, b"pong".to_vec()).await;
+    assert_eq!(received, b"pong");
+}
+
+#[tokio::test]
+async fn test_udp_discard() {
+    let server = EchoServer::new().with_udp(true).with_mode(Mode::Discard);
+    let hostname = spawn_app(7785, server).await;
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.send_to(b"ping", &hostname).await.unwrap();
+
+    let mut buf = [0u8; 64];
+    let reply = timeout(Duration::from_millis(300), socket.recv_from(&mut buf)).await;
+    assert!(reply.is_err());
+}
+
+#[tokio::test]
+async fn test_udp_chargen() {
+    let server = EchoServer::new().with_udp(true).with_mode(Mode::Chargen);
+    let hostname = spawn_app(7786, server).await;
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut buf = [0u8; 1024];
+    let mut lines = Vec::new();
+
+    /* consecutive datagrams continue the pattern */
+    for _ in 0..2 {
+        socket.send_to(b"", &hostname).await.unwrap();
+        let (len, _addr) = timeout(Duration::from_secs(1), socket.recv_from(&mut buf))
+            .await
+            .expect("No UDP reply")
+            .unwrap();
+        assert!(len <= 512);
+        assert_eq!(len % CHARGEN_LINE_LEN, 0);
+        lines.extend(
+            buf[..len]
+                .chunks(CHARGEN_LINE_LEN)
+                .map(|line| line.to_vec()),
+        );
+    }
+
+    for (i, line) in lines.iter().enumerate() {
+        assert_eq!(line[..], chargen_line(i)[..]);
+    }
+}
+
+#[tokio::test]
+async fn test_udp_chargen_throttled() {
+    let server = EchoServer::new().with_udp(true).with_mode(Mode::Chargen);
+    let hostname = spawn_app(7787, server).await;
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut buf = [0u8; 1024];
+
+    /* even without configured limits, a burst only gets the default rate */
+    for _ in 0..CHARGEN_UDP_RATE + 3 {
+        socket.send_to(b"", &hostname).await.unwrap();
+    }
+
+    let mut replies = 0;
+    while timeout(Duration::from_millis(300), socket.recv_from(&mut buf))
+        .await
+        .is_ok()
+    {
+        replies += 1;
+    }
+    assert_eq!(replies, CHARGEN_UDP_RATE);
+}
@@ -0,0 +1,146 @@
+use lru::LruCache;
+use num_bigint::BigUint;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::prime;
+
+pub const DEFAULT_CACHE_SIZE: usize = 4096;
+
+/* below this the Miller-Rabin test is cheaper than the bookkeeping */
+const MIN_CACHED_BITS: u64 = 64;
+
+#[derive(Debug)]
+struct PrimeCacheInner {
+    entries: Mutex<LruCache<BigUint, bool>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/*
+ * Primality results shared by all connections, least recently used are
+ * evicted first. The lock is not held while testing a number, so two clients
+ * asking for the same new number at once may both end up computing it.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct PrimeCache {
+    inner: Option<Arc<PrimeCacheInner>>,
+}
+
+impl PrimeCache {
+    /* a size of 0 disables caching */
+    pub fn new(size: usize) -> PrimeCache {
+        PrimeCache {
+            inner: NonZeroUsize::new(size).map(|size| {
+                Arc::new(PrimeCacheInner {
+                    entries: Mutex::new(LruCache::new(size)),
+                    hits: AtomicU64::new(0),
+                    misses: AtomicU64::new(0),
+                })
+            }),
+        }
+    }
+
+    pub fn is_prime(&self, n: &BigUint) -> bool {
+        let inner = match &self.inner {
+            Some(inner) if n.bits() > MIN_CACHED_BITS => inner,
+            _ => return prime::is_prime(n),
+        };
+
+        if let Some(prime) = inner.entries.lock().unwrap().get(n) {
+            inner.hits.fetch_add(1, Ordering::Relaxed);
+            return *prime;
+        }
+        inner.misses.fetch_add(1, Ordering::Relaxed);
+
+        let prime = prime::is_prime(n);
+        inner.entries.lock().unwrap().put(n.clone(), prime);
+
+        prime
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner
+            .as_ref()
+            .map_or(0, |inner| inner.entries.lock().unwrap().len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.inner
+            .as_ref()
+            .map_or(0, |inner| inner.hits.load(Ordering::Relaxed))
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.inner
+            .as_ref()
+            .map_or(0, |inner| inner.misses.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(n: &str) -> BigUint {
+        BigUint::parse_bytes(n.as_bytes(), 10).unwrap()
+    }
+
+    #[test]
+    fn test_hits_and_misses() {
+        let cache = PrimeCache::new(8);
+        let n = big("170141183460469231731687303715884105727");
+
+        assert!(cache.is_prime(&n));
+        assert!(cache.is_prime(&n));
+        assert!(!cache.is_prime(&big("1000000000000000000000009")));
+
+        assert_eq!(cache.hits(), 1);
+        assert_eq!(cache.misses(), 2);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_small_numbers_bypass() {
+        let cache = PrimeCache::new(8);
+
+        assert!(cache.is_prime(&BigUint::from(7u32)));
+        assert!(cache.is_prime(&BigUint::from(7u32)));
+
+        assert_eq!(cache.hits() + cache.misses(), 0);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_eviction() {
+        let cache = PrimeCache::new(2);
+        let numbers: Vec<BigUint> = (0..3u32)
+            .map(|i| big("1000000000000000000000000") + i)
+            .collect();
+
+        for n in &numbers {
+            cache.is_prime(n);
+        }
+        /* the oldest one was evicted and has to be computed again */
+        cache.is_prime(&numbers[0]);
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.hits(), 0);
+        assert_eq!(cache.misses(), 4);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let cache = PrimeCache::new(0);
+        let n = big("1000000000000000000000007");
+
+        assert!(cache.is_prime(&n));
+        assert!(cache.is_prime(&n));
+        assert_eq!(cache.hits() + cache.misses(), 0);
+    }
+}
@@ -0,0 +1,5 @@
+pub mod cache;
+pub mod methods;
+pub mod prime;
+pub mod protocol;
+pub mod server;
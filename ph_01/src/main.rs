@@ -1,12 +1,11 @@
-use anyhow::{anyhow, Error, Result};
 use clap::Parser;
-use primes;
-use serde::Serialize;
-use serde_json;
-use serde_json::Value;
-use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::net::{Shutdown, TcpListener, TcpStream};
-use std::thread;
+use ph_common::limits::{LimitArgs, Limits};
+use std::error::Error;
+use tokio::time::{interval, Duration};
+
+use protohackers::cache::{PrimeCache, DEFAULT_CACHE_SIZE};
+use protohackers::protocol::{ErrorMode, Registry};
+use protohackers::server::{PrimeTimeServer, DEFAULT_MAX_IN_FLIGHT};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -18,119 +17,60 @@ struct Args {
     /// IP to listen on
     #[arg(long, default_value_t = String::from("0.0.0.0"))]
     host: String,
-}
 
-fn main() {
-    let args = Args::parse();
+    /// Reply sent to a malformed request before disconnecting
+    #[arg(long, value_enum, default_value_t = ErrorMode::Strict)]
+    error_mode: ErrorMode,
 
-    let hostname = format!("{}:{}", args.host, args.port);
-    println!("Will start listening on {}", hostname);
+    /// Number of primality results remembered across connections, 0 disables the cache
+    #[arg(long, default_value_t = DEFAULT_CACHE_SIZE)]
+    cache_size: usize,
 
-    let listener = TcpListener::bind(hostname).expect("Unable to bind to socket");
+    /// Maximum number of requests from one client processed concurrently
+    #[arg(long, default_value_t = DEFAULT_MAX_IN_FLIGHT)]
+    max_in_flight: usize,
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                thread::spawn(|| {
-                    handle_client(stream);
-                });
-            }
-            Err(e) => {
-                println!("Error: {}", e);
-            }
-        }
-    }
+    #[command(flatten)]
+    limits: LimitArgs,
 }
 
-fn handle_client(stream: TcpStream) {
-    println!("New connection: {}", stream.peer_addr().unwrap());
-
-    let reader = BufReader::new(&stream);
-    let mut writer = BufWriter::new(&stream);
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
 
-    for line in reader.lines() {
-        dbg!(&line);
+    let hostname = format!("{}:{}", args.host, args.port);
+    println!("Will start listening on {hostname}");
 
-        let line = &line.unwrap();
-        if let Ok(out) = parse_line(&line) {
-            //dbg!(&out);
-            println!("out: {}", &out);
+    let cache = PrimeCache::new(args.cache_size);
+    tokio::spawn(report_cache(cache.clone()));
 
-            if let Err(_) = writer.write(&out.as_bytes()) {
-                stream.shutdown(Shutdown::Both).unwrap();
-                break;
-            }
+    let server = PrimeTimeServer::new()
+        .with_registry(Registry::builtin(cache))
+        .with_error_mode(args.error_mode)
+        .with_max_in_flight(args.max_in_flight)
+        .with_limits(Limits::new(args.limits));
+    server.run(hostname).await?;
 
-            if let Err(_) = writer.write(b"\n") {
-                stream.shutdown(Shutdown::Both).unwrap();
-                break;
-            }
-            writer.flush().unwrap();
-        } else {
-            if let Err(_) = writer.write(b"\n") {
-                stream.shutdown(Shutdown::Both).unwrap();
-                break;
-            }
-            writer.flush().unwrap();
-            stream.shutdown(Shutdown::Both).unwrap();
-            break;
-        }
-    }
-
-    println!("Client disconnected");
-    stream.shutdown(Shutdown::Write).unwrap_or(())
-}
-
-#[derive(Serialize)]
-struct PrimeResponse {
-    method: String,
-    prime: bool,
+    Ok(())
 }
 
-fn parse_line(input: &String) -> Result<String> {
-    println!("inp: {}", &input);
-    let json: serde_json::Result<Value> = serde_json::from_str(input);
-
-    let resp_ok = PrimeResponse {
-        method: "isPrime".to_owned(),
-        prime: true,
-    };
-    let resp_nok = PrimeResponse {
-        method: "isPrime".to_owned(),
-        prime: false,
-    };
-
-    match json {
-        Ok(json) => {
-            //dbg!(&json);
-
-            let method = json
-                .get("method")
-                .ok_or_else(|| anyhow!("Field method not found"))?;
-            if method != "isPrime" {
-                return Err(anyhow!("Field method is not isPrime"));
-            }
-
-            let number = json
-                .get("number")
-                .ok_or_else(|| anyhow!("Field number not found"))?;
-            if number.is_number() != true {
-                return Err(anyhow!("Field number is not a number"));
-            }
-
-            if number.is_u64() != true {
-                return Ok(serde_json::to_string(&resp_nok).unwrap());
-            }
-
-            if primes::is_prime(number.as_u64().unwrap()) {
-                Ok(serde_json::to_string(&resp_ok).unwrap())
-            } else {
-                Ok(serde_json::to_string(&resp_nok).unwrap())
-            }
-        }
-        Err(e) => {
-            println!("Err: {}", e);
-            Err(Error::new(e))
+/* logs the cache counters once a minute, whenever they changed */
+async fn report_cache(cache: PrimeCache) {
+    let mut ticker = interval(Duration::from_secs(60));
+    let mut last = (0, 0);
+
+    loop {
+        ticker.tick().await;
+
+        let current = (cache.hits(), cache.misses());
+        if current != last {
+            println!(
+                "Cache: {} entries, {} hits, {} misses",
+                cache.len(),
+                current.0,
+                current.1
+            );
+            last = current;
         }
     }
 }
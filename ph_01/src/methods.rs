@@ -0,0 +1,191 @@
+use anyhow::{anyhow, Result};
+use num_bigint::{BigInt, BigUint};
+use num_integer::Integer;
+use num_traits::{ToPrimitive, Zero};
+use serde::{Deserialize, Serialize};
+use serde_json::Number;
+use std::fmt::Display;
+
+use crate::cache::PrimeCache;
+use crate::prime;
+use crate::protocol::Method;
+
+/* arbitrary_precision numbers keep the literal exactly as the client sent it */
+fn integer(number: &Number, field: &str) -> Result<BigInt> {
+    prime::integer_literal(&number.to_string())
+        .ok_or_else(|| anyhow!("Field {field} is not an integer"))
+}
+
+/* for methods whose cost grows too fast, checked before building the integer */
+fn small_integer(number: &Number, field: &str) -> Result<BigInt> {
+    let digits = number
+        .to_string()
+        .bytes()
+        .filter(u8::is_ascii_digit)
+        .count();
+    if digits > prime::MAX_DIGITS {
+        return Err(anyhow!(
+            "Field {field} has more than {} digits",
+            prime::MAX_DIGITS
+        ));
+    }
+
+    integer(number, field)
+}
+
+fn to_number(n: impl Display) -> Number {
+    n.to_string()
+        .parse()
+        .expect("integers are valid JSON numbers")
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NumberRequest {
+    pub number: Number,
+}
+
+#[derive(Debug, Default)]
+pub struct IsPrime {
+    cache: PrimeCache,
+}
+
+impl IsPrime {
+    pub fn new(cache: PrimeCache) -> IsPrime {
+        IsPrime { cache }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct IsPrimeResponse {
+    pub prime: bool,
+}
+
+impl Method for IsPrime {
+    const NAME: &'static str = "isPrime";
+    const FIELDS: &'static [&'static str] = &["number"];
+
+    type Request = NumberRequest;
+    type Response = IsPrimeResponse;
+
+    fn call(&self, request: NumberRequest) -> Result<IsPrimeResponse> {
+        /* floats and negative numbers are never prime */
+        let prime = prime::integer_literal(&request.number.to_string())
+            .and_then(|n| n.to_biguint())
+            .is_some_and(|n| self.cache.is_prime(&n));
+
+        Ok(IsPrimeResponse { prime })
+    }
+}
+
+#[derive(Debug)]
+pub struct NextPrime;
+
+#[derive(Debug, Serialize)]
+pub struct NextPrimeResponse {
+    pub next: Number,
+}
+
+impl Method for NextPrime {
+    const NAME: &'static str = "nextPrime";
+    const FIELDS: &'static [&'static str] = &["number"];
+
+    type Request = NumberRequest;
+    type Response = NextPrimeResponse;
+
+    fn call(&self, request: NumberRequest) -> Result<NextPrimeResponse> {
+        let n = small_integer(&request.number, "number")?;
+
+        Ok(NextPrimeResponse {
+            next: to_number(prime::next_prime(&n)),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Factorize;
+
+#[derive(Debug, Serialize)]
+pub struct FactorizeResponse {
+    pub factors: Vec<u64>,
+}
+
+impl Method for Factorize {
+    const NAME: &'static str = "factorize";
+    const FIELDS: &'static [&'static str] = &["number"];
+
+    type Request = NumberRequest;
+    type Response = FactorizeResponse;
+
+    fn call(&self, request: NumberRequest) -> Result<FactorizeResponse> {
+        let n = small_integer(&request.number, "number")?
+            .to_u64()
+            .filter(|n| *n > 0)
+            .ok_or_else(|| anyhow!("Field number is not a positive 64 bit integer"))?;
+
+        Ok(FactorizeResponse {
+            factors: prime::factorize(n),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct IsPerfectSquare;
+
+#[derive(Debug, Serialize)]
+pub struct IsPerfectSquareResponse {
+    pub square: bool,
+}
+
+impl Method for IsPerfectSquare {
+    const NAME: &'static str = "isPerfectSquare";
+    const FIELDS: &'static [&'static str] = &["number"];
+
+    type Request = NumberRequest;
+    type Response = IsPerfectSquareResponse;
+
+    /* like isPrime, floats and negative numbers are simply not squares */
+    fn call(&self, request: NumberRequest) -> Result<IsPerfectSquareResponse> {
+        let square = prime::integer_literal(&request.number.to_string())
+            .and_then(|n| n.to_biguint())
+            .is_some_and(|n: BigUint| {
+                let root = n.sqrt();
+                &root * &root == n
+            });
+
+        Ok(IsPerfectSquareResponse { square })
+    }
+}
+
+#[derive(Debug)]
+pub struct Gcd;
+
+#[derive(Debug, Deserialize)]
+pub struct GcdRequest {
+    pub a: Number,
+    pub b: Number,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GcdResponse {
+    pub gcd: Number,
+}
+
+impl Method for Gcd {
+    const NAME: &'static str = "gcd";
+    const FIELDS: &'static [&'static str] = &["a", "b"];
+
+    type Request = GcdRequest;
+    type Response = GcdResponse;
+
+    fn call(&self, request: GcdRequest) -> Result<GcdResponse> {
+        let a = integer(&request.a, "a")?;
+        let b = integer(&request.b, "b")?;
+        if a.is_zero() && b.is_zero() {
+            return Err(anyhow!("gcd(0, 0) is undefined"));
+        }
+
+        Ok(GcdResponse {
+            gcd: to_number(a.gcd(&b)),
+        })
+    }
+}
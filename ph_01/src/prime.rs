@@ -0,0 +1,267 @@
+use num_bigint::{BigInt, BigUint};
+use num_integer::Integer;
+use num_traits::{One, Zero};
+
+/*
+ * Miller-Rabin with the first 20 primes as witnesses. Deterministic for
+ * n < 3.3 * 10^24 (the first 13 bases are enough there), above that the
+ * chance of a composite slipping through is below 4^-20.
+ */
+const WITNESSES: [u32; 20] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71,
+];
+
+pub fn is_prime(n: &BigUint) -> bool {
+    let one = BigUint::one();
+    let two = BigUint::from(2u32);
+
+    if *n < two {
+        return false;
+    }
+
+    for p in WITNESSES {
+        if (n % p).is_zero() {
+            return *n == BigUint::from(p);
+        }
+    }
+
+    let n_minus_one = n - &one;
+    let s = n_minus_one.trailing_zeros().unwrap_or(0);
+    let d = &n_minus_one >> s;
+
+    'witness: for a in WITNESSES {
+        let mut x = BigUint::from(a).modpow(&d, n);
+        if x == one || x == n_minus_one {
+            continue;
+        }
+
+        for _ in 1..s {
+            x = x.modpow(&two, n);
+            if x == n_minus_one {
+                continue 'witness;
+            }
+        }
+
+        return false;
+    }
+
+    true
+}
+
+/*
+ * Methods whose cost grows too fast refuse integers with more digits than
+ * this. nextPrime has to test a few hundred candidates of that size, which
+ * already takes a worker a while.
+ */
+pub const MAX_DIGITS: usize = 300;
+
+/*
+ * Value of a JSON number literal if it is typed as an integer. A fraction or
+ * an exponent makes it a float, as it would for serde_json without
+ * arbitrary_precision, so "7.0" and "7e0" are not integers even though their
+ * value is.
+ */
+pub fn integer_literal(literal: &str) -> Option<BigInt> {
+    let digits = literal.strip_prefix('-').unwrap_or(literal);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    BigInt::parse_bytes(literal.as_bytes(), 10)
+}
+
+/*
+ * Primality of a JSON number literal. Floats and negative numbers are never
+ * prime, "7" is but "7.0", "7e0" and "-7" are not.
+ */
+pub fn is_prime_literal(literal: &str) -> bool {
+    integer_literal(literal)
+        .and_then(|n| n.to_biguint())
+        .is_some_and(|n| is_prime(&n))
+}
+
+/* smallest prime strictly greater than n */
+pub fn next_prime(n: &BigInt) -> BigUint {
+    let mut candidate = match n.to_biguint() {
+        Some(n) if n >= BigUint::from(2u32) => n + 1u32,
+        _ => return BigUint::from(2u32),
+    };
+
+    if candidate.is_even() {
+        candidate += 1u32;
+    }
+    while !is_prime(&candidate) {
+        candidate += 2u32;
+    }
+
+    candidate
+}
+
+fn mul_mod(a: u64, b: u64, m: u64) -> u64 {
+    (a as u128 * b as u128 % m as u128) as u64
+}
+
+fn is_prime_u64(n: u64) -> bool {
+    is_prime(&BigUint::from(n))
+}
+
+/* Pollard's rho with Brent's cycle detection, n must be an odd composite */
+fn find_divisor(n: u64) -> u64 {
+    for c in 1.. {
+        let f = |x: u64| (mul_mod(x, x, n) + c) % n;
+        let (mut x, mut y, mut d) = (2, 2, 1);
+
+        while d == 1 {
+            x = f(x);
+            y = f(f(y));
+            d = x.abs_diff(y).gcd(&n);
+        }
+        if d != n {
+            return d;
+        }
+    }
+
+    unreachable!()
+}
+
+/*
+ * Prime factors of n in ascending order, repeated by multiplicity.
+ * Limited to 64 bit numbers, above that factoring can take forever.
+ */
+pub fn factorize(mut n: u64) -> Vec<u64> {
+    let mut factors = Vec::new();
+
+    for p in WITNESSES {
+        let p = p as u64;
+        while n > 1 && n.is_multiple_of(p) {
+            factors.push(p);
+            n /= p;
+        }
+    }
+
+    let mut stack = vec![n];
+    while let Some(n) = stack.pop() {
+        if n == 1 {
+            continue;
+        }
+        if is_prime_u64(n) {
+            factors.push(n);
+            continue;
+        }
+
+        let d = find_divisor(n);
+        stack.push(d);
+        stack.push(n / d);
+    }
+
+    factors.sort_unstable();
+    factors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(n: &str) -> BigUint {
+        BigUint::parse_bytes(n.as_bytes(), 10).unwrap()
+    }
+
+    #[test]
+    fn test_small_numbers() {
+        let primes: Vec<u32> = (0..100u32)
+            .filter(|n| is_prime(&BigUint::from(*n)))
+            .collect();
+
+        assert_eq!(
+            primes,
+            vec![
+                2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79,
+                83, 89, 97
+            ]
+        );
+    }
+
+    #[test]
+    fn test_large_primes() {
+        assert!(is_prime(&big("1000000000000000000000007")));
+        assert!(is_prime(&big("18446744073709551629")));
+        /* Mersenne primes 2^89 - 1 and 2^127 - 1 */
+        assert!(is_prime(&big("618970019642690137449562111")));
+        assert!(is_prime(&big("170141183460469231731687303715884105727")));
+    }
+
+    #[test]
+    fn test_large_composites() {
+        assert!(!is_prime(&big("1000000000000000000000009")));
+        /* (2^61 - 1) * (2^31 - 1) */
+        assert!(!is_prime(&big("4951760154835678088235319297")));
+        /* strong pseudoprimes to all bases up to 23 and up to 37 */
+        assert!(!is_prime(&big("3825123056546413051")));
+        assert!(!is_prime(&big("318665857834031151167461")));
+        /* Carmichael number */
+        assert!(!is_prime(&big("561")));
+    }
+
+    #[test]
+    fn test_literals() {
+        assert!(is_prime_literal("7"));
+        assert!(is_prime_literal("1000000000000000000000007"));
+
+        assert!(!is_prime_literal("7.0"));
+        assert!(!is_prime_literal("0.7e1"));
+        assert!(!is_prime_literal("70E-1"));
+        assert!(!is_prime_literal("7e0"));
+        assert!(!is_prime_literal("7.5"));
+        assert!(!is_prime_literal("-7"));
+        assert!(!is_prime_literal("-0"));
+        assert!(!is_prime_literal("0"));
+        assert!(!is_prime_literal("0.0"));
+        assert!(!is_prime_literal("1"));
+        assert!(!is_prime_literal("7e1"));
+        assert!(!is_prime_literal("7e-1"));
+        assert!(!is_prime_literal("7e9223372036854775807"));
+        assert!(!is_prime_literal("7e99999999999999999999"));
+        assert!(!is_prime_literal("7e-99999999999999999999"));
+    }
+
+    #[test]
+    fn test_integer_literals() {
+        assert_eq!(integer_literal("-7"), Some(BigInt::from(-7)));
+        assert_eq!(integer_literal("007"), Some(BigInt::from(7)));
+        assert_eq!(integer_literal("-0"), Some(BigInt::zero()));
+        assert_eq!(integer_literal("7.0"), None);
+        assert_eq!(integer_literal("1.5e2"), None);
+        assert_eq!(integer_literal("7e9223372036854775807"), None);
+        assert_eq!(integer_literal(""), None);
+        assert_eq!(integer_literal("-"), None);
+        assert_eq!(
+            integer_literal(&"9".repeat(MAX_DIGITS + 1)),
+            BigInt::parse_bytes("9".repeat(MAX_DIGITS + 1).as_bytes(), 10)
+        );
+    }
+
+    #[test]
+    fn test_next_prime() {
+        assert_eq!(next_prime(&BigInt::from(-5)), BigUint::from(2u32));
+        assert_eq!(next_prime(&BigInt::from(2)), BigUint::from(3u32));
+        assert_eq!(next_prime(&BigInt::from(7)), BigUint::from(11u32));
+        assert_eq!(next_prime(&BigInt::from(89)), BigUint::from(97u32));
+        assert_eq!(
+            next_prime(&BigInt::from(1_000_000_000_000_000_000_000_000u128)),
+            big("1000000000000000000000007")
+        );
+    }
+
+    #[test]
+    fn test_factorize() {
+        assert_eq!(factorize(1), Vec::<u64>::new());
+        assert_eq!(factorize(2), vec![2]);
+        assert_eq!(factorize(360), vec![2, 2, 2, 3, 3, 5]);
+        assert_eq!(factorize(561), vec![3, 11, 17]);
+        /* (2^31 - 1) * (2^32 - 5) */
+        assert_eq!(
+            factorize(2147483647 * 4294967291),
+            vec![2147483647, 4294967291]
+        );
+        assert_eq!(factorize(18446744073709551557), vec![18446744073709551557]);
+    }
+}
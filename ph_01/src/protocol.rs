@@ -0,0 +1,307 @@
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::cache::PrimeCache;
+use crate::methods::{Factorize, Gcd, IsPerfectSquare, IsPrime, NextPrime};
+
+/*
+ * A request method: `{"method":NAME,...}` is deserialized into Request and
+ * answered with `{"method":NAME,...}` carrying the fields of Response.
+ */
+pub trait Method {
+    const NAME: &'static str;
+    /* number fields the request has to carry, checked before deserializing */
+    const FIELDS: &'static [&'static str];
+
+    type Request: DeserializeOwned;
+    type Response: Serialize;
+
+    fn call(&self, request: Self::Request) -> Result<Self::Response>;
+}
+
+#[derive(Serialize)]
+struct Reply<R> {
+    method: &'static str,
+    #[serde(flatten)]
+    response: R,
+}
+
+type Handler = Box<dyn Fn(&str) -> Result<String> + Send + Sync>;
+
+struct Entry {
+    fields: &'static [&'static str],
+    handler: Handler,
+}
+
+/*
+ * The request is parsed again from the line itself. Going through Value would
+ * turn an integer like 1000000000000000000000000 into the float 1e24.
+ */
+fn dispatch<M: Method>(method: &M, input: &str) -> Result<String> {
+    let request = serde_json::from_str(input)?;
+    let reply = Reply {
+        method: M::NAME,
+        response: method.call(request)?,
+    };
+
+    Ok(serde_json::to_string(&reply)?)
+}
+
+pub struct Registry {
+    methods: HashMap<&'static str, Entry>,
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<_> = self.methods.keys().collect();
+        names.sort();
+        f.debug_struct("Registry").field("methods", &names).finish()
+    }
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry {
+            methods: HashMap::new(),
+        }
+    }
+
+    /* everything this server knows about */
+    pub fn builtin(cache: PrimeCache) -> Registry {
+        Registry::new()
+            .with_method(IsPrime::new(cache))
+            .with_method(NextPrime)
+            .with_method(Factorize)
+            .with_method(IsPerfectSquare)
+            .with_method(Gcd)
+    }
+
+    pub fn with_method<M: Method + Send + Sync + 'static>(mut self, method: M) -> Registry {
+        self.methods.insert(
+            M::NAME,
+            Entry {
+                fields: M::FIELDS,
+                handler: Box::new(move |input| dispatch(&method, input)),
+            },
+        );
+        self
+    }
+
+    /*
+     * Answers one request line. Any error means the request was malformed,
+     * whether it is not JSON, names an unknown method, lacks a field or the
+     * method itself refuses the input.
+     */
+    pub fn handle(&self, input: &str) -> Result<String> {
+        println!("inp: {}", &input);
+        let json: Value = serde_json::from_str(input)?;
+
+        let method = json
+            .get("method")
+            .ok_or_else(|| anyhow!("Field method not found"))?;
+        let method = method
+            .as_str()
+            .ok_or_else(|| anyhow!("Field method is not a string"))?;
+        let entry = self
+            .methods
+            .get(method)
+            .ok_or_else(|| anyhow!("Unknown method {method}"))?;
+
+        for field in entry.fields {
+            let value = json
+                .get(field)
+                .ok_or_else(|| anyhow!("Field {field} not found"))?;
+            if !value.is_number() {
+                return Err(anyhow!("Field {field} is not a number"));
+            }
+        }
+
+        (entry.handler)(input)
+    }
+}
+
+/* what a malformed request gets back before the connection is closed */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ErrorMode {
+    /// An empty line, as little as the protocol allows
+    #[default]
+    Strict,
+    /// A JSON object naming the problem, for debugging clients
+    Json,
+}
+
+impl ErrorMode {
+    pub fn response(&self, error: &anyhow::Error) -> String {
+        match self {
+            ErrorMode::Strict => String::new(),
+            ErrorMode::Json => serde_json::json!({ "error": error.to_string() }).to_string(),
+        }
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry::builtin(PrimeCache::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_bigint::BigUint;
+    use serde::Deserialize;
+
+    fn parse_line(input: &str) -> Result<String> {
+        Registry::default().handle(input)
+    }
+
+    fn is_prime(number: &str) -> Result<String> {
+        parse_line(&format!("{{\"method\":\"isPrime\",\"number\":{number}}}"))
+    }
+
+    const PRIME: &str = "{\"method\":\"isPrime\",\"prime\":true}";
+    const NOT_PRIME: &str = "{\"method\":\"isPrime\",\"prime\":false}";
+
+    #[test]
+    fn test_huge_numbers() {
+        assert_eq!(is_prime("1000000000000000000000007").unwrap(), PRIME);
+        assert_eq!(is_prime("1000000000000000000000009").unwrap(), NOT_PRIME);
+        assert_eq!(
+            is_prime("170141183460469231731687303715884105727").unwrap(),
+            PRIME
+        );
+        assert_eq!(is_prime(&"9".repeat(300)).unwrap(), NOT_PRIME);
+        assert_eq!(is_prime(&"9".repeat(301)).unwrap(), NOT_PRIME);
+        assert_eq!(
+            is_prime(&format!("{}.5", "9".repeat(400))).unwrap(),
+            NOT_PRIME
+        );
+        /* Mersenne prime 2^1279 - 1, 386 digits */
+        let mersenne = (BigUint::from(1u32) << 1279u32) - 1u32;
+        assert_eq!(is_prime(&mersenne.to_string()).unwrap(), PRIME);
+    }
+
+    #[test]
+    fn test_negative_numbers() {
+        assert_eq!(is_prime("-7").unwrap(), NOT_PRIME);
+        assert_eq!(is_prime("-1000000000000000000000007").unwrap(), NOT_PRIME);
+    }
+
+    #[test]
+    fn test_floats() {
+        assert_eq!(is_prime("7.5").unwrap(), NOT_PRIME);
+        assert_eq!(is_prime("7.0").unwrap(), NOT_PRIME);
+        assert_eq!(is_prime("0.5").unwrap(), NOT_PRIME);
+    }
+
+    #[test]
+    fn test_exponent_form() {
+        assert_eq!(is_prime("2.3e1").unwrap(), NOT_PRIME);
+        assert_eq!(is_prime("7e0").unwrap(), NOT_PRIME);
+        assert_eq!(is_prime("1e3").unwrap(), NOT_PRIME);
+        assert_eq!(is_prime("13E-1").unwrap(), NOT_PRIME);
+        assert_eq!(is_prime("1e400").unwrap(), NOT_PRIME);
+        assert_eq!(is_prime("7e9223372036854775807").unwrap(), NOT_PRIME);
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(is_prime("\"7\"").is_err());
+        assert!(parse_line("{\"method\":\"isPrime\"}").is_err());
+        assert!(parse_line("{\"method\":\"isOdd\",\"number\":7}").is_err());
+        assert!(parse_line("{\"method\":\"isPrime\",\"number\":7").is_err());
+        assert!(parse_line("[\"isPrime\",7]").is_err());
+        assert!(parse_line("{\"method\":7,\"number\":7}").is_err());
+        assert!(Registry::new()
+            .handle("{\"method\":\"isPrime\",\"number\":7}")
+            .is_err());
+    }
+
+    #[test]
+    fn test_other_methods() {
+        assert_eq!(
+            parse_line("{\"method\":\"nextPrime\",\"number\":1000000000000000000000000}").unwrap(),
+            "{\"method\":\"nextPrime\",\"next\":1000000000000000000000007}"
+        );
+        assert_eq!(
+            parse_line("{\"method\":\"factorize\",\"number\":360}").unwrap(),
+            "{\"method\":\"factorize\",\"factors\":[2,2,2,3,3,5]}"
+        );
+        assert_eq!(
+            parse_line("{\"method\":\"isPerfectSquare\",\"number\":100}").unwrap(),
+            "{\"method\":\"isPerfectSquare\",\"square\":true}"
+        );
+        assert_eq!(
+            parse_line("{\"method\":\"isPerfectSquare\",\"number\":1e2}").unwrap(),
+            "{\"method\":\"isPerfectSquare\",\"square\":false}"
+        );
+        assert_eq!(
+            parse_line("{\"method\":\"gcd\",\"a\":-12,\"b\":18}").unwrap(),
+            "{\"method\":\"gcd\",\"gcd\":6}"
+        );
+
+        assert!(parse_line("{\"method\":\"nextPrime\",\"number\":7.5}").is_err());
+        assert!(parse_line("{\"method\":\"nextPrime\",\"number\":7e9223372036854775807}").is_err());
+        assert!(parse_line("{\"method\":\"factorize\",\"number\":0}").is_err());
+        assert!(parse_line("{\"method\":\"factorize\",\"number\":18446744073709551616}").is_err());
+        assert!(parse_line("{\"method\":\"gcd\",\"a\":12}").is_err());
+    }
+
+    #[derive(Deserialize)]
+    struct Echo {
+        text: String,
+    }
+
+    #[derive(Serialize)]
+    struct EchoResponse {
+        text: String,
+    }
+
+    struct EchoMethod;
+
+    impl Method for EchoMethod {
+        const NAME: &'static str = "echo";
+        const FIELDS: &'static [&'static str] = &[];
+
+        type Request = Echo;
+        type Response = EchoResponse;
+
+        fn call(&self, request: Echo) -> Result<EchoResponse> {
+            Ok(EchoResponse { text: request.text })
+        }
+    }
+
+    #[test]
+    fn test_custom_method() {
+        let registry = Registry::new().with_method(EchoMethod);
+
+        assert_eq!(
+            registry
+                .handle("{\"method\":\"echo\",\"text\":\"hi\"}")
+                .unwrap(),
+            "{\"method\":\"echo\",\"text\":\"hi\"}"
+        );
+        assert!(registry.handle("{\"method\":\"echo\",\"text\":7}").is_err());
+    }
+
+    #[test]
+    fn test_error_mode() {
+        let error = parse_line("{\"method\":\"isPrime\"}").unwrap_err();
+
+        assert_eq!(ErrorMode::Strict.response(&error), "");
+        assert_eq!(
+            ErrorMode::Json.response(&error),
+            "{\"error\":\"Field number not found\"}"
+        );
+
+        let error = parse_line("{\"method\":\"say \\\"hi\\\"\"}").unwrap_err();
+        assert_eq!(
+            ErrorMode::Json.response(&error),
+            "{\"error\":\"Unknown method say \\\"hi\\\"\"}"
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use futures::sink::SinkExt;
+use futures::stream::FuturesOrdered;
+use futures::FutureExt;
+use ph_common::limits::Limits;
+use std::error::Error;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep_until, Instant};
+use tokio_stream::StreamExt;
+use tokio_util::codec::{Framed, LinesCodec};
+
+use crate::protocol::{ErrorMode, Registry};
+
+/* far beyond any sensible request, only there so a line cannot grow forever */
+pub const MAX_LINE_LENGTH: usize = 1024 * 1024;
+
+/* requests of one client being worked on before we stop reading more */
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 64;
+
+type Pending = BoxFuture<'static, Result<String>>;
+
+#[derive(Debug)]
+pub struct PrimeTimeServer {
+    registry: Arc<Registry>,
+    error_mode: ErrorMode,
+    max_in_flight: usize,
+    limits: Limits,
+}
+
+impl Default for PrimeTimeServer {
+    fn default() -> Self {
+        PrimeTimeServer {
+            registry: Arc::new(Registry::default()),
+            error_mode: ErrorMode::default(),
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            limits: Limits::default(),
+        }
+    }
+}
+
+impl PrimeTimeServer {
+    pub fn new() -> PrimeTimeServer {
+        PrimeTimeServer::default()
+    }
+
+    pub fn with_registry(mut self, registry: Registry) -> PrimeTimeServer {
+        self.registry = Arc::new(registry);
+        self
+    }
+
+    pub fn with_error_mode(mut self, error_mode: ErrorMode) -> PrimeTimeServer {
+        self.error_mode = error_mode;
+        self
+    }
+
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> PrimeTimeServer {
+        self.max_in_flight = max_in_flight.max(1);
+        self
+    }
+
+    /* cap connections, throttle chatty clients and drop idle ones */
+    pub fn with_limits(mut self, limits: Limits) -> PrimeTimeServer {
+        self.limits = limits;
+        self
+    }
+
+    pub async fn run(self, hostname: String) -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind(hostname).await?;
+
+        loop {
+            let (stream, addr) = listener.accept().await?;
+            let guard = match self.limits.acquire(addr.ip()) {
+                Some(guard) => guard,
+                None => {
+                    println!(
+                        "Rejected connection from {addr}, {} rejected so far",
+                        self.limits.rejected()
+                    );
+                    continue;
+                }
+            };
+            let limits = self.limits.clone();
+            let registry = self.registry.clone();
+            let error_mode = self.error_mode;
+            let max_in_flight = self.max_in_flight;
+
+            tokio::spawn(async move {
+                let _guard = guard;
+
+                println!("New connection: {addr}");
+                if let Err(e) = PrimeTimeServer::handle_client(
+                    stream,
+                    registry,
+                    error_mode,
+                    max_in_flight,
+                    limits,
+                )
+                .await
+                {
+                    println!("Error occurred: {e:?}");
+                }
+                println!("Disconnected: {addr}");
+            });
+        }
+    }
+
+    /*
+     * Requests are checked on the blocking pool as soon as they are read, so
+     * a huge number does not hold up the ones behind it. The answers are
+     * still written in request order, and everything that is ready goes out
+     * with a single flush. A malformed request gets its error reply once all
+     * the requests before it have been answered, then the connection closes.
+     */
+    pub async fn handle_client(
+        stream: TcpStream,
+        registry: Arc<Registry>,
+        error_mode: ErrorMode,
+        max_in_flight: usize,
+        limits: Limits,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut codec = Framed::new(
+            limits.wrap(stream),
+            LinesCodec::new_with_max_length(MAX_LINE_LENGTH),
+        );
+        let mut rate_limiter = limits.rate_limiter();
+        let mut pending: FuturesOrdered<Pending> = FuturesOrdered::new();
+        let mut reading = true;
+        /* over the rate, reading pauses while the answers still go out */
+        let mut resume_at = None;
+
+        loop {
+            tokio::select! {
+                _ = sleep_until(resume_at.unwrap_or_else(Instant::now)), if resume_at.is_some() => {
+                    resume_at = None;
+                }
+                line = codec.next(), if reading && resume_at.is_none() && pending.len() < max_in_flight => {
+                    match line {
+                        Some(Ok(line)) => {
+                            resume_at = rate_limiter.resume_at();
+                            let registry = registry.clone();
+                            let check = tokio::task::spawn_blocking(move || registry.handle(&line));
+                            pending.push_back(Box::pin(async move { check.await? }));
+                        }
+                        /* too long, not UTF-8 or the idle timeout; answer like malformed */
+                        Some(Err(e)) => {
+                            println!("Error reading line: {e:?}");
+                            pending.push_back(Box::pin(async move { Err(anyhow!(e)) }));
+                            reading = false;
+                        }
+                        None => reading = false,
+                    }
+                }
+                Some(result) = pending.next() => {
+                    let mut result = result;
+                    let mut malformed = false;
+
+                    loop {
+                        match result {
+                            Ok(out) => codec.feed(out).await?,
+                            Err(e) => {
+                                println!("Malformed request: {e}");
+                                codec.feed(error_mode.response(&e)).await?;
+                                malformed = true;
+                                break;
+                            }
+                        }
+                        match pending.next().now_or_never() {
+                            Some(Some(next)) => result = next,
+                            _ => break,
+                        }
+                    }
+                    SinkExt::<String>::flush(&mut codec).await?;
+
+                    if malformed {
+                        break;
+                    }
+                }
+                else => break,
+            }
+        }
+
+        codec.get_mut().get_mut().shutdown().await?;
+
+        Ok(())
+    }
+}
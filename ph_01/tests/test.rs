@@ -0,0 +1,228 @@
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout, Duration};
+
+use ph_common::limits::{LimitArgs, Limits};
+use protohackers::cache::PrimeCache;
+use protohackers::protocol::{ErrorMode, Registry};
+use protohackers::server::{PrimeTimeServer, MAX_LINE_LENGTH};
+
+async fn spawn_app(port: u16, server: PrimeTimeServer) -> String {
+    let hostname = format!("127.0.0.1:{port}");
+    let listen = hostname.clone();
+    tokio::spawn(async move {
+        let _ = server.run(listen).await;
+    });
+    sleep(Duration::from_millis(100)).await;
+
+    hostname
+}
+
+fn request(number: &str) -> String {
+    format!("{{\"method\":\"isPrime\",\"number\":{number}}}\n")
+}
+
+fn response(prime: bool) -> String {
+    format!("{{\"method\":\"isPrime\",\"prime\":{prime}}}")
+}
+
+/* sends everything at once, then reads until the server closes */
+async fn session(hostname: &str, payload: String) -> Vec<String> {
+    let mut stream = TcpStream::connect(hostname).await.unwrap();
+    stream.write_all(payload.as_bytes()).await.unwrap();
+    stream.shutdown().await.unwrap();
+
+    let mut received = String::new();
+    timeout(
+        Duration::from_secs(10),
+        stream.read_to_string(&mut received),
+    )
+    .await
+    .expect("Server did not close the connection")
+    .unwrap();
+
+    received.split_terminator('\n').map(String::from).collect()
+}
+
+#[tokio::test]
+async fn test_single_request() {
+    let hostname = spawn_app(7777, PrimeTimeServer::new()).await;
+
+    let stream = TcpStream::connect(&hostname).await.unwrap();
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    writer.write_all(request("7").as_bytes()).await.unwrap();
+    assert_eq!(lines.next_line().await.unwrap().unwrap(), response(true));
+
+    writer.write_all(request("8").as_bytes()).await.unwrap();
+    assert_eq!(lines.next_line().await.unwrap().unwrap(), response(false));
+}
+
+#[tokio::test]
+async fn test_pipelined_order() {
+    let hostname = spawn_app(7778, PrimeTimeServer::new()).await;
+
+    /* the slow huge prime first, so the cheap ones finish before it */
+    let mut numbers = vec!["170141183460469231731687303715884105727".to_string()];
+    numbers.extend((0..200).map(|n| n.to_string()));
+
+    let payload: String = numbers.iter().map(|n| request(n)).collect();
+    let received = session(&hostname, payload).await;
+
+    let expected: Vec<String> = [true]
+        .into_iter()
+        .chain((0..200u32).map(|n| n > 1 && (2..n).all(|d| n % d != 0)))
+        .map(response)
+        .collect();
+    assert_eq!(received, expected);
+}
+
+#[tokio::test]
+async fn test_malformed_after_pipelined() {
+    let hostname = spawn_app(7779, PrimeTimeServer::new()).await;
+
+    let payload = [
+        request("2"),
+        request("4"),
+        "{\"method\":\"isPrime\"}\n".to_string(),
+        request("5"),
+    ]
+    .concat();
+    let received = session(&hostname, payload).await;
+
+    assert_eq!(
+        received,
+        vec![response(true), response(false), String::new()]
+    );
+}
+
+#[tokio::test]
+async fn test_limited_in_flight() {
+    let hostname = spawn_app(7780, PrimeTimeServer::new().with_max_in_flight(1)).await;
+
+    let payload: String = (0..50).map(|n| request(&n.to_string())).collect();
+    let received = session(&hostname, payload).await;
+
+    assert_eq!(received.len(), 50);
+    assert_eq!(received[2], response(true));
+    assert_eq!(received[49], response(false));
+}
+
+#[tokio::test]
+async fn test_long_lines() {
+    let hostname = spawn_app(7781, PrimeTimeServer::new()).await;
+
+    /* a long but valid request still gets its answer */
+    let payload = [request(&"1".repeat(20 * 1024)), request("7")].concat();
+    let received = session(&hostname, payload).await;
+    assert_eq!(received, vec![response(false), response(true)]);
+
+    let payload = request(&"1".repeat(MAX_LINE_LENGTH));
+    let received = session(&hostname, payload).await;
+    assert_eq!(received, vec![String::new()]);
+}
+
+#[tokio::test]
+async fn test_mixed_methods() {
+    let hostname = spawn_app(7782, PrimeTimeServer::new()).await;
+
+    let payload = [
+        "{\"method\":\"factorize\",\"number\":4294967297}\n",
+        "{\"method\":\"isPrime\",\"number\":97}\n",
+        "{\"method\":\"gcd\",\"a\":48,\"b\":180}\n",
+        "{\"method\":\"nextPrime\",\"number\":13}\n",
+        "{\"method\":\"isPerfectSquare\",\"number\":15}\n",
+    ]
+    .concat();
+    let received = session(&hostname, payload).await;
+
+    assert_eq!(
+        received,
+        vec![
+            "{\"method\":\"factorize\",\"factors\":[641,6700417]}",
+            "{\"method\":\"isPrime\",\"prime\":true}",
+            "{\"method\":\"gcd\",\"gcd\":12}",
+            "{\"method\":\"nextPrime\",\"next\":17}",
+            "{\"method\":\"isPerfectSquare\",\"square\":false}",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_json_errors() {
+    let server = PrimeTimeServer::new().with_error_mode(ErrorMode::Json);
+    let hostname = spawn_app(7783, server).await;
+
+    let payload = [request("3"), "{\"method\":\"isPrime\"}\n".to_string()].concat();
+    let received = session(&hostname, payload).await;
+
+    assert_eq!(
+        received,
+        vec![
+            response(true),
+            "{\"error\":\"Field number not found\"}".to_string()
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_cache_shared_across_connections() {
+    let cache = PrimeCache::new(16);
+    let server = PrimeTimeServer::new().with_registry(Registry::builtin(cache.clone()));
+    let hostname = spawn_app(7784, server).await;
+
+    let number = "170141183460469231731687303715884105727";
+    for _ in 0..2 {
+        let received = session(&hostname, request(number)).await;
+        assert_eq!(received, vec![response(true)]);
+    }
+
+    assert_eq!(cache.misses(), 1);
+    assert_eq!(cache.hits(), 1);
+}
+
+#[tokio::test]
+async fn test_too_many_digits() {
+    let hostname = spawn_app(7785, PrimeTimeServer::new()).await;
+
+    /* isPrime answers however long the number, only nextPrime is capped */
+    let number = format!("1{}", "0".repeat(300));
+    let payload = [
+        request(&number),
+        request(&format!("{number}.5")),
+        format!("{{\"method\":\"nextPrime\",\"number\":{number}}}\n"),
+        "{\"method\":\"nextPrime\",\"number\":17}\n".to_string(),
+    ]
+    .concat();
+    let received = session(&hostname, payload).await;
+
+    assert_eq!(
+        received,
+        vec![response(false), response(false), String::new()]
+    );
+}
+
+#[tokio::test]
+async fn test_throttled_answers() {
+    let limits = Limits::new(LimitArgs {
+        max_messages_per_sec: Some(1),
+        ..Default::default()
+    });
+    let hostname = spawn_app(7786, PrimeTimeServer::new().with_limits(limits)).await;
+
+    let stream = TcpStream::connect(&hostname).await.unwrap();
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let payload: String = ["2", "3", "4", "5"].map(request).concat();
+    writer.write_all(payload.as_bytes()).await.unwrap();
+
+    /* reading is paused over the rate, answering what was read is not */
+    for prime in [true, true] {
+        let line = timeout(Duration::from_millis(500), lines.next_line())
+            .await
+            .expect("Answer held back by the rate limit")
+            .unwrap();
+        assert_eq!(line.unwrap(), response(prime));
+    }
+}
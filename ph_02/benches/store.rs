@@ -0,0 +1,59 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::collections::BTreeMap;
+
+use protohackers::store::PriceStore;
+
+const SIZES: [i32; 3] = [1_000, 100_000, 1_000_000];
+
+fn btreemap_mean(prices: &BTreeMap<i32, i32>, time_min: i32, time_max: i32) -> i32 {
+    let (sum, cnt) = prices
+        .range(time_min..=time_max)
+        .fold((0i64, 0i64), |(sum, cnt), (_, price)| {
+            (sum + *price as i64, cnt + 1)
+        });
+
+    if cnt > 0 {
+        (sum / cnt) as i32
+    } else {
+        0
+    }
+}
+
+/* queries over the middle half of the inserted timestamps */
+fn query(c: &mut Criterion) {
+    let mut group = c.benchmark_group("query");
+
+    for size in SIZES {
+        let mut store = PriceStore::new();
+        let mut prices = BTreeMap::new();
+        for timestamp in 0..size {
+            store.insert(timestamp, timestamp % 1000).unwrap();
+            prices.insert(timestamp, timestamp % 1000);
+        }
+        let (time_min, time_max) = (size / 4, size / 4 * 3);
+
+        group.bench_with_input(BenchmarkId::new("PriceStore", size), &size, |b, _| {
+            b.iter(|| store.mean(black_box(time_min), black_box(time_max)))
+        });
+        group.bench_with_input(BenchmarkId::new("BTreeMap", size), &size, |b, _| {
+            b.iter(|| btreemap_mean(&prices, black_box(time_min), black_box(time_max)))
+        });
+    }
+
+    group.finish();
+}
+
+fn insert(c: &mut Criterion) {
+    c.bench_function("insert 100000", |b| {
+        b.iter(|| {
+            let mut store = PriceStore::new();
+            for timestamp in 0..100_000 {
+                store.insert(black_box(timestamp), timestamp).unwrap();
+            }
+            store
+        })
+    });
+}
+
+criterion_group!(benches, query, insert);
+criterion_main!(benches);
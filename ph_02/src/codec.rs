@@ -0,0 +1,410 @@
+use bytes::{Buf, BufMut, BytesMut};
+use std::io;
+use thiserror::Error;
+
+use tokio_util::codec::Decoder;
+use tokio_util::codec::Encoder;
+
+pub const MSG_INSERT: u8 = b'I';
+pub const MSG_QUERY: u8 = b'Q';
+/* extension, only understood with persistent sessions enabled */
+pub const MSG_SESSION: u8 = b'S';
+/* extensions, only understood with --extensions */
+pub const MSG_MIN: u8 = b'n';
+pub const MSG_MAX: u8 = b'x';
+pub const MSG_COUNT: u8 = b'c';
+pub const MSG_MEDIAN: u8 = b'm';
+pub const MSG_SUM: u8 = b's';
+pub const MSG_DELETE: u8 = b'D';
+
+/* every request is a type byte followed by two big endian i32 values */
+pub const MSG_LEN: usize = 1 + 4 + 4;
+pub const SESSION_NAME_LEN: usize = MSG_LEN - 1;
+
+/*
+ * Aggregates over a time range, answered like Query with a big endian i32,
+ * except Sum which gets an i64.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    Min,
+    Max,
+    Count,
+    Median,
+    Sum,
+}
+
+impl Aggregate {
+    fn msg_type(&self) -> u8 {
+        match self {
+            Aggregate::Min => MSG_MIN,
+            Aggregate::Max => MSG_MAX,
+            Aggregate::Count => MSG_COUNT,
+            Aggregate::Median => MSG_MEDIAN,
+            Aggregate::Sum => MSG_SUM,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Msg {
+    Insert {
+        timestamp: i32,
+        price: i32,
+    },
+    Query {
+        time_min: i32,
+        time_max: i32,
+    },
+    Session {
+        name: [u8; SESSION_NAME_LEN],
+    },
+    Aggregate {
+        aggregate: Aggregate,
+        time_min: i32,
+        time_max: i32,
+    },
+    /* no reply, like Insert */
+    Delete {
+        time_min: i32,
+        time_max: i32,
+    },
+}
+
+#[derive(Debug)]
+pub struct MeansCodec {}
+
+#[derive(Debug, Error)]
+pub enum MeansCodecError {
+    #[error("IO error")]
+    IoError(#[from] io::Error),
+    #[error("Unknown message type: {0:#04x}")]
+    UnknownType(u8),
+}
+
+impl MeansCodec {
+    pub fn new() -> MeansCodec {
+        MeansCodec {}
+    }
+}
+
+impl Default for MeansCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/* requests, only needed by clients */
+impl Encoder<Msg> for MeansCodec {
+    type Error = MeansCodecError;
+
+    fn encode(&mut self, item: Msg, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        dst.reserve(MSG_LEN);
+
+        match item {
+            Msg::Insert { timestamp, price } => {
+                dst.put_u8(MSG_INSERT);
+                dst.put_i32(timestamp);
+                dst.put_i32(price);
+            }
+            Msg::Query { time_min, time_max } => {
+                dst.put_u8(MSG_QUERY);
+                dst.put_i32(time_min);
+                dst.put_i32(time_max);
+            }
+            Msg::Session { name } => {
+                dst.put_u8(MSG_SESSION);
+                dst.put_slice(&name);
+            }
+            Msg::Aggregate {
+                aggregate,
+                time_min,
+                time_max,
+            } => {
+                dst.put_u8(aggregate.msg_type());
+                dst.put_i32(time_min);
+                dst.put_i32(time_max);
+            }
+            Msg::Delete { time_min, time_max } => {
+                dst.put_u8(MSG_DELETE);
+                dst.put_i32(time_min);
+                dst.put_i32(time_max);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/* the mean price answering a query */
+impl Encoder<i32> for MeansCodec {
+    type Error = MeansCodecError;
+
+    fn encode(&mut self, item: i32, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        dst.reserve(4);
+        dst.put_i32(item);
+
+        Ok(())
+    }
+}
+
+/* the sum of prices answering the Sum extension */
+impl Encoder<i64> for MeansCodec {
+    type Error = MeansCodecError;
+
+    fn encode(&mut self, item: i64, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        dst.reserve(8);
+        dst.put_i64(item);
+
+        Ok(())
+    }
+}
+
+impl Decoder for MeansCodec {
+    type Error = MeansCodecError;
+    type Item = Msg;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.len() < MSG_LEN {
+            /* Not enough data */
+            return Ok(None);
+        }
+
+        let msg_type = src.get_u8();
+        if msg_type == MSG_SESSION {
+            let mut name = [0u8; SESSION_NAME_LEN];
+            src.copy_to_slice(&mut name);
+            return Ok(Some(Msg::Session { name }));
+        }
+
+        let val1 = src.get_i32();
+        let val2 = src.get_i32();
+
+        match msg_type {
+            MSG_INSERT => Ok(Some(Msg::Insert {
+                timestamp: val1,
+                price: val2,
+            })),
+            MSG_QUERY => Ok(Some(Msg::Query {
+                time_min: val1,
+                time_max: val2,
+            })),
+            MSG_DELETE => Ok(Some(Msg::Delete {
+                time_min: val1,
+                time_max: val2,
+            })),
+            MSG_MIN | MSG_MAX | MSG_COUNT | MSG_MEDIAN | MSG_SUM => {
+                let aggregate = match msg_type {
+                    MSG_MIN => Aggregate::Min,
+                    MSG_MAX => Aggregate::Max,
+                    MSG_COUNT => Aggregate::Count,
+                    MSG_MEDIAN => Aggregate::Median,
+                    _ => Aggregate::Sum,
+                };
+
+                Ok(Some(Msg::Aggregate {
+                    aggregate,
+                    time_min: val1,
+                    time_max: val2,
+                }))
+            }
+            _ => Err(MeansCodecError::UnknownType(msg_type)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_decode() {
+        let data = b"\x49\x00\x00\x30\x39\x00\x00\x00\x65";
+
+        let mut codec = MeansCodec::new();
+        let mut input_buf = BytesMut::from(&data[..]);
+
+        let output = codec.decode(&mut input_buf).unwrap();
+        assert_eq!(
+            output,
+            Some(Msg::Insert {
+                timestamp: 12345,
+                price: 101
+            })
+        );
+        assert!(input_buf.is_empty());
+    }
+
+    #[test]
+    fn test_query_decode_negative() {
+        let data = b"\x51\xff\xff\xff\xfe\x00\x00\x00\x05";
+
+        let mut codec = MeansCodec::new();
+        let mut input_buf = BytesMut::from(&data[..]);
+
+        let output = codec.decode(&mut input_buf).unwrap();
+        assert_eq!(
+            output,
+            Some(Msg::Query {
+                time_min: -2,
+                time_max: 5
+            })
+        );
+    }
+
+    #[test]
+    fn test_split_frames() {
+        let data = b"\x49\x00\x00\x30\x39\x00\x00\x00\x65\x51\x00\x00\x03\xe8\x00\x01\x86\xa0";
+
+        let mut codec = MeansCodec::new();
+        let mut input_buf = BytesMut::with_capacity(32);
+        let mut decoded = Vec::new();
+
+        /* one byte at a time, frames only come out once complete */
+        for byte in data {
+            input_buf.put_u8(*byte);
+            if let Some(msg) = codec.decode(&mut input_buf).unwrap() {
+                decoded.push(msg);
+            }
+            if decoded.len() == 1 {
+                assert!(input_buf.len() < MSG_LEN);
+            }
+        }
+
+        assert_eq!(
+            decoded,
+            vec![
+                Msg::Insert {
+                    timestamp: 12345,
+                    price: 101
+                },
+                Msg::Query {
+                    time_min: 1000,
+                    time_max: 100000
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_type() {
+        let data = b"\x58\x00\x00\x00\x01\x00\x00\x00\x02";
+
+        let mut codec = MeansCodec::new();
+        let mut input_buf = BytesMut::from(&data[..8]);
+
+        /* not decided until the whole frame is there */
+        match codec.decode(&mut input_buf) {
+            Ok(None) => {}
+            output => panic!("Decoded partial message: {:?}", output),
+        }
+
+        input_buf.put_u8(data[8]);
+        match codec.decode(&mut input_buf) {
+            Err(MeansCodecError::UnknownType(0x58)) => {}
+            output => panic!("Expected unknown type error: {:?}", output),
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut codec = MeansCodec::new();
+        let mut output_buf = BytesMut::with_capacity(32);
+
+        codec
+            .encode(
+                Msg::Query {
+                    time_min: 1000,
+                    time_max: 100000,
+                },
+                &mut output_buf,
+            )
+            .unwrap();
+        codec.encode(-101, &mut output_buf).unwrap();
+
+        assert_eq!(
+            output_buf.to_vec(),
+            b"\x51\x00\x00\x03\xe8\x00\x01\x86\xa0\xff\xff\xff\x9b"
+        );
+    }
+
+    #[test]
+    fn test_session_roundtrip() {
+        let mut codec = MeansCodec::new();
+        let mut buf = BytesMut::with_capacity(32);
+
+        codec
+            .encode(
+                Msg::Session {
+                    name: *b"alice\0\0\0",
+                },
+                &mut buf,
+            )
+            .unwrap();
+        assert_eq!(buf.to_vec(), b"Salice\0\0\0");
+
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Msg::Session {
+                name: *b"alice\0\0\0"
+            })
+        );
+    }
+
+    #[test]
+    fn test_extensions_roundtrip() {
+        let mut codec = MeansCodec::new();
+        let mut buf = BytesMut::with_capacity(64);
+
+        let aggregates = [
+            Aggregate::Min,
+            Aggregate::Max,
+            Aggregate::Count,
+            Aggregate::Median,
+            Aggregate::Sum,
+        ];
+        for aggregate in aggregates {
+            let msg = Msg::Aggregate {
+                aggregate,
+                time_min: -1,
+                time_max: 1,
+            };
+            codec.encode(msg, &mut buf).unwrap();
+        }
+        let msg = Msg::Delete {
+            time_min: 3,
+            time_max: 4,
+        };
+        codec.encode(msg, &mut buf).unwrap();
+
+        assert_eq!(&buf[..MSG_LEN], b"n\xff\xff\xff\xff\x00\x00\x00\x01");
+        assert_eq!(&buf[5 * MSG_LEN..], b"D\x00\x00\x00\x03\x00\x00\x00\x04");
+
+        for aggregate in aggregates {
+            assert_eq!(
+                codec.decode(&mut buf).unwrap(),
+                Some(Msg::Aggregate {
+                    aggregate,
+                    time_min: -1,
+                    time_max: 1
+                })
+            );
+        }
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Msg::Delete {
+                time_min: 3,
+                time_max: 4
+            })
+        );
+    }
+
+    #[test]
+    fn test_sum_encode() {
+        let mut codec = MeansCodec::new();
+        let mut buf = BytesMut::with_capacity(8);
+
+        codec.encode(-2i64, &mut buf).unwrap();
+        assert_eq!(buf.to_vec(), b"\xff\xff\xff\xff\xff\xff\xff\xfe");
+    }
+}
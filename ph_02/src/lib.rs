@@ -0,0 +1,4 @@
+pub mod codec;
+pub mod persist;
+pub mod server;
+pub mod store;
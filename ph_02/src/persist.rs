@@ -0,0 +1,256 @@
+use bytes::BytesMut;
+use std::collections::HashSet;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::codec::{MeansCodec, Msg, MSG_LEN, SESSION_NAME_LEN};
+
+/* the log holds the Insert and Delete messages exactly as received */
+const RECORD_LEN: usize = MSG_LEN;
+
+/*
+ * Session names are up to 8 characters of [A-Za-z0-9_-], padded with NUL
+ * bytes. They end up in file names, so anything else is refused.
+ */
+pub fn parse_name(raw: &[u8; SESSION_NAME_LEN]) -> Option<String> {
+    let len = raw.iter().position(|b| *b == 0).unwrap_or(raw.len());
+    let (name, padding) = raw.split_at(len);
+
+    if name.is_empty()
+        || padding.iter().any(|b| *b != 0)
+        || !name
+            .iter()
+            .all(|b| b.is_ascii_alphanumeric() || *b == b'_' || *b == b'-')
+    {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(name).to_string())
+}
+
+#[derive(Debug)]
+struct SessionDirInner {
+    path: PathBuf,
+    active: Mutex<HashSet<String>>,
+}
+
+/* directory with one append-only price log per session name */
+#[derive(Debug, Clone)]
+pub struct SessionDir {
+    inner: Arc<SessionDirInner>,
+}
+
+impl SessionDir {
+    pub fn new(path: impl Into<PathBuf>) -> SessionDir {
+        SessionDir {
+            inner: Arc::new(SessionDirInner {
+                path: path.into(),
+                active: Mutex::new(HashSet::new()),
+            }),
+        }
+    }
+
+    /* a session can only be used by one client at a time */
+    pub fn claim(&self, name: &str) -> Option<SessionClaim> {
+        let mut active = self.inner.active.lock().unwrap();
+        if !active.insert(name.to_string()) {
+            return None;
+        }
+
+        Some(SessionClaim {
+            dir: self.clone(),
+            name: name.to_string(),
+        })
+    }
+
+    fn release(&self, name: &str) {
+        self.inner.active.lock().unwrap().remove(name);
+    }
+}
+
+/* keeps the session reserved until dropped */
+#[derive(Debug)]
+pub struct SessionClaim {
+    dir: SessionDir,
+    name: String,
+}
+
+impl SessionClaim {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir.inner.path.join(format!("{}.prices", self.name))
+    }
+
+    /* a partial record at the end is what a crash mid-write leaves behind */
+    pub async fn load(&self) -> io::Result<Vec<Msg>> {
+        let mut data = match tokio::fs::read(self.path()).await {
+            Ok(data) => BytesMut::from(&data[..]),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut codec = MeansCodec::new();
+        let mut records = Vec::new();
+        while let Some(msg) = codec
+            .decode(&mut data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        {
+            records.push(msg);
+        }
+
+        Ok(records)
+    }
+
+    pub async fn open_log(&self) -> io::Result<PriceLog> {
+        tokio::fs::create_dir_all(&self.dir.inner.path).await?;
+
+        let path = self.path();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+
+        /* drop a partial record so the next one starts on a record boundary */
+        let len = file.metadata().await?.len();
+        if len % RECORD_LEN as u64 != 0 {
+            file.set_len(len - len % RECORD_LEN as u64).await?;
+        }
+
+        Ok(PriceLog { file })
+    }
+}
+
+impl Drop for SessionClaim {
+    fn drop(&mut self) {
+        self.dir.release(&self.name);
+    }
+}
+
+#[derive(Debug)]
+pub struct PriceLog {
+    file: File,
+}
+
+impl PriceLog {
+    pub async fn append(&mut self, msg: Msg) -> io::Result<()> {
+        let mut record = BytesMut::with_capacity(RECORD_LEN);
+        MeansCodec::new()
+            .encode(msg, &mut record)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        /* flushed right away, a tokio File would otherwise write it later */
+        self.file.write_all(&record).await?;
+        self.file.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_name() {
+        assert_eq!(parse_name(b"alice\0\0\0").as_deref(), Some("alice"));
+        assert_eq!(parse_name(b"a_b-1234").as_deref(), Some("a_b-1234"));
+
+        assert_eq!(parse_name(b"\0\0\0\0\0\0\0\0"), None);
+        assert_eq!(parse_name(b"../etc\0\0"), None);
+        assert_eq!(parse_name(b"ab\0cd\0\0\0"), None);
+        assert_eq!(parse_name(b"caf\xc3\xa9\0\0\0"), None);
+    }
+
+    #[test]
+    fn test_claim() {
+        let dir = SessionDir::new("/nonexistent");
+
+        let claim = dir.claim("alice").unwrap();
+        assert!(dir.claim("alice").is_none());
+        assert!(dir.claim("bob").is_some());
+
+        drop(claim);
+        assert!(dir.claim("alice").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_log_roundtrip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = SessionDir::new(tmp.path().join("sessions"));
+        let claim = dir.claim("alice").unwrap();
+
+        assert_eq!(claim.load().await.unwrap(), vec![]);
+
+        let records = vec![
+            Msg::Insert {
+                timestamp: 1,
+                price: 100,
+            },
+            Msg::Insert {
+                timestamp: -2,
+                price: i32::MIN,
+            },
+            Msg::Delete {
+                time_min: 0,
+                time_max: 5,
+            },
+        ];
+
+        let mut log = claim.open_log().await.unwrap();
+        for record in records.iter() {
+            log.append(*record).await.unwrap();
+        }
+        drop(log);
+
+        assert_eq!(claim.load().await.unwrap(), records);
+    }
+
+    #[tokio::test]
+    async fn test_log_flushed() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = SessionDir::new(tmp.path());
+        let claim = dir.claim("alice").unwrap();
+        let insert = |timestamp, price| Msg::Insert { timestamp, price };
+
+        /* the log stays open, a reconnecting client reads it meanwhile */
+        let mut log = claim.open_log().await.unwrap();
+        log.append(insert(1, 100)).await.unwrap();
+        assert_eq!(claim.load().await.unwrap(), vec![insert(1, 100)]);
+
+        log.append(insert(2, 200)).await.unwrap();
+        assert_eq!(
+            claim.load().await.unwrap(),
+            vec![insert(1, 100), insert(2, 200)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_partial_record() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = SessionDir::new(tmp.path());
+        let claim = dir.claim("alice").unwrap();
+
+        std::fs::write(
+            tmp.path().join("alice.prices"),
+            b"I\x00\x00\x00\x01\x00\x00\x00\x64I\x00\x00",
+        )
+        .unwrap();
+        let insert = |timestamp, price| Msg::Insert { timestamp, price };
+        assert_eq!(claim.load().await.unwrap(), vec![insert(1, 100)]);
+
+        let mut log = claim.open_log().await.unwrap();
+        log.append(insert(2, 200)).await.unwrap();
+        drop(log);
+
+        assert_eq!(
+            claim.load().await.unwrap(),
+            vec![insert(1, 100), insert(2, 200)]
+        );
+    }
+}
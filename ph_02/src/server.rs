@@ -0,0 +1,269 @@
+use futures::sink::SinkExt;
+use ph_common::limits::Limits;
+use std::error::Error;
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_stream::StreamExt;
+use tokio_util::codec::Framed;
+
+use crate::codec::{Aggregate, MeansCodec, Msg};
+use crate::persist::{parse_name, PriceLog, SessionClaim, SessionDir};
+use crate::store::{DuplicatePolicy, PriceStore};
+
+#[derive(Debug, Default)]
+pub struct MeansServer {
+    duplicates: DuplicatePolicy,
+    sessions: Option<SessionDir>,
+    extensions: bool,
+    limits: Limits,
+}
+
+/*
+ * Prices inserted by one client, nobody else can see them. Persistent
+ * sessions are restored from and logged to their session file instead.
+ */
+#[derive(Debug, Default)]
+pub struct Session {
+    prices: PriceStore,
+    log: Option<PriceLog>,
+    claim: Option<SessionClaim>,
+}
+
+impl Session {
+    pub fn new(duplicates: DuplicatePolicy) -> Session {
+        Session {
+            prices: PriceStore::new().with_policy(duplicates),
+            ..Default::default()
+        }
+    }
+
+    pub async fn persistent(
+        claim: SessionClaim,
+        duplicates: DuplicatePolicy,
+    ) -> Result<Session, Box<dyn Error>> {
+        let mut prices = PriceStore::new().with_policy(duplicates);
+
+        /* rejected duplicates never made it to the log, so they are ignored here */
+        for msg in claim.load().await? {
+            match msg {
+                Msg::Insert { timestamp, price } => {
+                    let _ = prices.insert(timestamp, price);
+                }
+                Msg::Delete { time_min, time_max } => {
+                    prices.delete(time_min, time_max);
+                }
+                _ => {}
+            }
+        }
+        println!(
+            "Restored {} prices of session {}",
+            prices.len(),
+            claim.name()
+        );
+
+        Ok(Session {
+            prices,
+            log: Some(claim.open_log().await?),
+            claim: Some(claim),
+        })
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.claim.as_ref().map(|claim| claim.name())
+    }
+
+    pub async fn insert(&mut self, timestamp: i32, price: i32) -> Result<(), Box<dyn Error>> {
+        self.prices.insert(timestamp, price)?;
+        if let Some(log) = &mut self.log {
+            log.append(Msg::Insert { timestamp, price }).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn delete(&mut self, time_min: i32, time_max: i32) -> Result<(), Box<dyn Error>> {
+        self.prices.delete(time_min, time_max);
+        if let Some(log) = &mut self.log {
+            log.append(Msg::Delete { time_min, time_max }).await?;
+        }
+
+        Ok(())
+    }
+
+    pub fn query(&self, time_min: i32, time_max: i32) -> i32 {
+        self.prices.mean(time_min, time_max)
+    }
+
+    /* 0 for an empty range, like the mean; counts and sums are clamped */
+    pub fn aggregate(&mut self, aggregate: Aggregate, time_min: i32, time_max: i32) -> i64 {
+        let prices = &mut self.prices;
+
+        match aggregate {
+            Aggregate::Min => prices.min(time_min, time_max).unwrap_or(0) as i64,
+            Aggregate::Max => prices.max(time_min, time_max).unwrap_or(0) as i64,
+            Aggregate::Median => prices.median(time_min, time_max).unwrap_or(0) as i64,
+            Aggregate::Count => {
+                let (_, cnt) = prices.range(time_min, time_max);
+                i32::try_from(cnt).unwrap_or(i32::MAX) as i64
+            }
+            Aggregate::Sum => {
+                let (total, _) = prices.range(time_min, time_max);
+                i64::try_from(total).unwrap_or(if total < 0 { i64::MIN } else { i64::MAX })
+            }
+        }
+    }
+}
+
+impl MeansServer {
+    pub fn new() -> MeansServer {
+        MeansServer::default()
+    }
+
+    pub fn with_duplicates(mut self, duplicates: DuplicatePolicy) -> MeansServer {
+        self.duplicates = duplicates;
+        self
+    }
+
+    /* let clients pick a persistent session with the 'S' extension message */
+    pub fn with_session_dir(mut self, path: Option<PathBuf>) -> MeansServer {
+        self.sessions = path.map(SessionDir::new);
+        self
+    }
+
+    /* min/max/count/median/sum queries and range deletes, see codec */
+    pub fn with_extensions(mut self, extensions: bool) -> MeansServer {
+        self.extensions = extensions;
+        self
+    }
+
+    /* cap connections, throttle chatty clients and drop idle ones */
+    pub fn with_limits(mut self, limits: Limits) -> MeansServer {
+        self.limits = limits;
+        self
+    }
+
+    pub async fn run(self, hostname: String) -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind(hostname).await?;
+
+        loop {
+            let (stream, addr) = listener.accept().await?;
+            let guard = match self.limits.acquire(addr.ip()) {
+                Some(guard) => guard,
+                None => {
+                    println!(
+                        "Rejected connection from {addr}, {} rejected so far",
+                        self.limits.rejected()
+                    );
+                    continue;
+                }
+            };
+            let limits = self.limits.clone();
+            let duplicates = self.duplicates;
+            let sessions = self.sessions.clone();
+            let extensions = self.extensions;
+
+            tokio::spawn(async move {
+                let _guard = guard;
+
+                println!("New connection: {addr}");
+                if let Err(e) =
+                    MeansServer::handle_client(stream, duplicates, sessions, extensions, limits)
+                        .await
+                {
+                    println!("Error occurred: {e:?}");
+                }
+                println!("Disconnected: {addr}");
+            });
+        }
+    }
+
+    pub async fn handle_client(
+        stream: TcpStream,
+        duplicates: DuplicatePolicy,
+        sessions: Option<SessionDir>,
+        extensions: bool,
+        limits: Limits,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut codec = Framed::new(limits.wrap(stream), MeansCodec::new());
+        let mut rate_limiter = limits.rate_limiter();
+        let mut session = Session::new(duplicates);
+        let mut first = true;
+
+        while let Some(msg) = codec.next().await {
+            /* unknown types, a truncated last frame and idle clients all end the session */
+            let msg = match msg {
+                Ok(msg) => msg,
+                Err(e) => {
+                    println!("Err: {e}");
+                    break;
+                }
+            };
+            rate_limiter.acquire().await;
+
+            let is_first = std::mem::replace(&mut first, false);
+            match msg {
+                Msg::Insert { timestamp, price } => {
+                    println!("Processing insert: {timestamp}:{price}");
+                    if let Err(e) = session.insert(timestamp, price).await {
+                        println!("Err: {e}");
+                        break;
+                    }
+                }
+                Msg::Query { time_min, time_max } => {
+                    println!("Processing query: {time_min}:{time_max}");
+                    codec.send(session.query(time_min, time_max)).await?;
+                }
+                Msg::Aggregate { .. } | Msg::Delete { .. } if !extensions => {
+                    println!("Err: Extensions are not enabled");
+                    break;
+                }
+                Msg::Aggregate {
+                    aggregate,
+                    time_min,
+                    time_max,
+                } => {
+                    println!("Processing {aggregate:?}: {time_min}:{time_max}");
+                    let result = session.aggregate(aggregate, time_min, time_max);
+                    match aggregate {
+                        Aggregate::Sum => codec.send(result).await?,
+                        _ => codec.send(result as i32).await?,
+                    }
+                }
+                Msg::Delete { time_min, time_max } => {
+                    println!("Processing delete: {time_min}:{time_max}");
+                    if let Err(e) = session.delete(time_min, time_max).await {
+                        println!("Err: {e}");
+                        break;
+                    }
+                }
+                Msg::Session { name } => {
+                    /* without a session dir this is just another unknown message */
+                    let Some(sessions) = &sessions else {
+                        println!("Err: Sessions are not enabled");
+                        break;
+                    };
+                    if !is_first {
+                        println!("Err: Session has to be chosen before anything else");
+                        break;
+                    }
+                    let Some(name) = parse_name(&name) else {
+                        println!("Err: Invalid session name {name:?}");
+                        break;
+                    };
+                    let Some(claim) = sessions.claim(&name) else {
+                        println!("Err: Session {name} is already in use");
+                        break;
+                    };
+
+                    println!("Processing session: {name}");
+                    session = Session::persistent(claim, duplicates).await?;
+                }
+            }
+        }
+
+        codec.get_mut().get_mut().shutdown().await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,617 @@
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use thiserror::Error;
+
+type Link = Option<Box<Node>>;
+
+#[derive(Debug)]
+struct Node {
+    timestamp: i32,
+    price: i32,
+    priority: u64,
+    /* aggregates over the whole subtree, this node included */
+    count: usize,
+    sum: i128,
+    min: i32,
+    max: i32,
+    left: Link,
+    right: Link,
+}
+
+impl Node {
+    fn new(timestamp: i32, price: i32, priority: u64) -> Box<Node> {
+        Box::new(Node {
+            timestamp,
+            price,
+            priority,
+            count: 1,
+            sum: price as i128,
+            min: price,
+            max: price,
+            left: None,
+            right: None,
+        })
+    }
+
+    fn update(&mut self) {
+        self.count = 1 + count(&self.left) + count(&self.right);
+        self.sum = self.price as i128 + sum(&self.left) + sum(&self.right);
+        self.min = [&self.left, &self.right]
+            .into_iter()
+            .flatten()
+            .fold(self.price, |min, node| min.min(node.min));
+        self.max = [&self.left, &self.right]
+            .into_iter()
+            .flatten()
+            .fold(self.price, |max, node| max.max(node.max));
+    }
+
+    fn collect_prices(&self, prices: &mut Vec<i32>) {
+        for child in [&self.left, &self.right].into_iter().flatten() {
+            child.collect_prices(prices);
+        }
+        prices.push(self.price);
+    }
+}
+
+fn count(link: &Link) -> usize {
+    link.as_ref().map_or(0, |node| node.count)
+}
+
+fn sum(link: &Link) -> i128 {
+    link.as_ref().map_or(0, |node| node.sum)
+}
+
+/* splits into the nodes before timestamp and the rest */
+fn split(link: Link, timestamp: i32) -> (Link, Link) {
+    match link {
+        None => (None, None),
+        Some(mut node) => {
+            if node.timestamp < timestamp {
+                let (left, right) = split(node.right.take(), timestamp);
+                node.right = left;
+                node.update();
+                (Some(node), right)
+            } else {
+                let (left, right) = split(node.left.take(), timestamp);
+                node.left = right;
+                node.update();
+                (left, Some(node))
+            }
+        }
+    }
+}
+
+/* every timestamp in left has to be smaller than those in right */
+fn merge(left: Link, right: Link) -> Link {
+    match (left, right) {
+        (None, right) => right,
+        (left, None) => left,
+        (Some(mut left), Some(mut right)) => {
+            if left.priority > right.priority {
+                left.right = merge(left.right.take(), Some(right));
+                left.update();
+                Some(left)
+            } else {
+                right.left = merge(Some(left), right.left.take());
+                right.update();
+                Some(right)
+            }
+        }
+    }
+}
+
+/* what to do when a client inserts a timestamp it already used */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DuplicatePolicy {
+    /// Treat it as an error and disconnect the client
+    Reject,
+    /// Ignore the new price
+    KeepFirst,
+    /// Replace the earlier price
+    #[default]
+    KeepLast,
+    /// Keep both, each counting towards the mean
+    KeepAll,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("Duplicate timestamp {0}")]
+pub struct DuplicateTimestamp(pub i32);
+
+/*
+ * Mean of count prices summing up to total, truncated toward zero. The mean
+ * of i32 prices always fits an i32, the clamp only makes that explicit.
+ */
+pub fn mean(total: i128, count: usize) -> i32 {
+    if count == 0 {
+        return 0;
+    }
+
+    let mean = total / count as i128;
+    i32::try_from(mean).unwrap_or(if mean < 0 { i32::MIN } else { i32::MAX })
+}
+
+/*
+ * Prices ordered by timestamp in a treap, every node carrying the sum and
+ * count of its subtree. Inserts and range means take O(log n) expected time
+ * instead of walking over every price in the range.
+ */
+#[derive(Debug)]
+pub struct PriceStore {
+    root: Link,
+    seed: u64,
+    policy: DuplicatePolicy,
+}
+
+impl Default for PriceStore {
+    fn default() -> Self {
+        PriceStore {
+            root: None,
+            /* random per store so clients cannot line up a degenerate tree */
+            seed: RandomState::new().build_hasher().finish() | 1,
+            policy: DuplicatePolicy::default(),
+        }
+    }
+}
+
+impl PriceStore {
+    pub fn new() -> PriceStore {
+        PriceStore::default()
+    }
+
+    pub fn with_policy(mut self, policy: DuplicatePolicy) -> PriceStore {
+        self.policy = policy;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        count(&self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /* xorshift, the priorities only have to look random to the input */
+    fn priority(&mut self) -> u64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+
+    /* splits off the nodes with time_min <= timestamp <= time_max */
+    fn split_range(&mut self, time_min: i32, time_max: i32) -> (Link, Link, Link) {
+        let (before, rest) = split(self.root.take(), time_min);
+        let (inside, after) = match time_max.checked_add(1) {
+            Some(next) => split(rest, next),
+            None => (rest, None),
+        };
+
+        (before, inside, after)
+    }
+
+    /* runs f on the subtree holding the range, None when it is empty */
+    fn with_range<R>(
+        &mut self,
+        time_min: i32,
+        time_max: i32,
+        f: impl FnOnce(&Node) -> R,
+    ) -> Option<R> {
+        if time_min > time_max {
+            return None;
+        }
+
+        let (before, inside, after) = self.split_range(time_min, time_max);
+        let result = inside.as_deref().map(f);
+        self.root = merge(merge(before, inside), after);
+
+        result
+    }
+
+    /* the store is left unchanged when the duplicate is rejected */
+    pub fn insert(&mut self, timestamp: i32, price: i32) -> Result<(), DuplicateTimestamp> {
+        let node = Some(Node::new(timestamp, price, self.priority()));
+        let (before, equal, after) = self.split_range(timestamp, timestamp);
+
+        let (equal, result) = match (equal, self.policy) {
+            (None, _) => (node, Ok(())),
+            (equal, DuplicatePolicy::Reject) => (equal, Err(DuplicateTimestamp(timestamp))),
+            (equal, DuplicatePolicy::KeepFirst) => (equal, Ok(())),
+            (_, DuplicatePolicy::KeepLast) => (node, Ok(())),
+            (equal, DuplicatePolicy::KeepAll) => (merge(equal, node), Ok(())),
+        };
+        self.root = merge(merge(before, equal), after);
+
+        result
+    }
+
+    /* with KeepAll any one of the prices at this timestamp */
+    pub fn get(&self, timestamp: i32) -> Option<i32> {
+        let mut link = &self.root;
+
+        while let Some(node) = link {
+            link = match timestamp.cmp(&node.timestamp) {
+                Ordering::Less => &node.left,
+                Ordering::Greater => &node.right,
+                Ordering::Equal => return Some(node.price),
+            };
+        }
+
+        None
+    }
+
+    /* sum and count of the prices at or before timestamp */
+    fn prefix(&self, timestamp: i32) -> (i128, usize) {
+        let (mut total, mut cnt) = (0, 0);
+        let mut link = &self.root;
+
+        while let Some(node) = link {
+            if node.timestamp <= timestamp {
+                total += sum(&node.left) + node.price as i128;
+                cnt += count(&node.left) + 1;
+                link = &node.right;
+            } else {
+                link = &node.left;
+            }
+        }
+
+        (total, cnt)
+    }
+
+    /* sum and count of the prices with time_min <= timestamp <= time_max */
+    pub fn range(&self, time_min: i32, time_max: i32) -> (i128, usize) {
+        if time_min > time_max {
+            return (0, 0);
+        }
+
+        let (upper_sum, upper_cnt) = self.prefix(time_max);
+        let (lower_sum, lower_cnt) = match time_min.checked_sub(1) {
+            Some(before) => self.prefix(before),
+            None => (0, 0),
+        };
+
+        (upper_sum - lower_sum, upper_cnt - lower_cnt)
+    }
+
+    /* 0 when the range is empty or backwards */
+    pub fn mean(&self, time_min: i32, time_max: i32) -> i32 {
+        let (total, cnt) = self.range(time_min, time_max);
+        mean(total, cnt)
+    }
+
+    pub fn min(&mut self, time_min: i32, time_max: i32) -> Option<i32> {
+        self.with_range(time_min, time_max, |node| node.min)
+    }
+
+    pub fn max(&mut self, time_min: i32, time_max: i32) -> Option<i32> {
+        self.with_range(time_min, time_max, |node| node.max)
+    }
+
+    /*
+     * Lower and upper middle averaged like the mean for an even count. Unlike
+     * the other aggregates this is linear in the number of prices in range.
+     */
+    pub fn median(&mut self, time_min: i32, time_max: i32) -> Option<i32> {
+        self.with_range(time_min, time_max, |node| {
+            let mut prices = Vec::with_capacity(node.count);
+            node.collect_prices(&mut prices);
+
+            let len = prices.len();
+            let (lower, upper, _) = prices.select_nth_unstable(len / 2);
+            match lower.iter().max() {
+                Some(lower) if len % 2 == 0 => mean(*lower as i128 + *upper as i128, 2),
+                _ => *upper,
+            }
+        })
+    }
+
+    /* returns how many prices were removed */
+    pub fn delete(&mut self, time_min: i32, time_max: i32) -> usize {
+        if time_min > time_max {
+            return 0;
+        }
+
+        let (before, inside, after) = self.split_range(time_min, time_max);
+        self.root = merge(before, after);
+
+        count(&inside)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    /* the BTreeMap scan the store replaced */
+    fn reference_mean(prices: &BTreeMap<i32, i32>, time_min: i32, time_max: i32) -> i32 {
+        if time_min > time_max {
+            return 0;
+        }
+
+        let (sum, cnt) = prices
+            .range(time_min..=time_max)
+            .fold((0i64, 0i64), |(sum, cnt), (_, price)| {
+                (sum + *price as i64, cnt + 1)
+            });
+
+        if cnt > 0 {
+            (sum / cnt) as i32
+        } else {
+            0
+        }
+    }
+
+    #[test]
+    fn test_example_session() {
+        let mut store = PriceStore::new();
+        store.insert(12345, 101).unwrap();
+        store.insert(12346, 102).unwrap();
+        store.insert(12347, 100).unwrap();
+        store.insert(40960, 5).unwrap();
+
+        assert_eq!(store.mean(12288, 16384), 101);
+        assert_eq!(store.range(0, i32::MAX), (308, 4));
+    }
+
+    #[test]
+    fn test_empty_and_backwards() {
+        let mut store = PriceStore::new();
+        assert_eq!(store.mean(i32::MIN, i32::MAX), 0);
+
+        store.insert(10, 100).unwrap();
+        assert_eq!(store.mean(20, 10), 0);
+        assert_eq!(store.mean(11, 20), 0);
+        assert_eq!(store.mean(10, 10), 100);
+    }
+
+    #[test]
+    fn test_overwrite() {
+        let mut store = PriceStore::new();
+        store.insert(10, 100).unwrap();
+        store.insert(10, 50).unwrap();
+
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get(10), Some(50));
+        assert_eq!(store.mean(0, 20), 50);
+    }
+
+    #[test]
+    fn test_extremes() {
+        let mut store = PriceStore::new();
+        store.insert(i32::MIN, i32::MAX).unwrap();
+        store.insert(i32::MAX, i32::MAX).unwrap();
+        store.insert(0, i32::MAX).unwrap();
+
+        assert_eq!(store.mean(i32::MIN, i32::MAX), i32::MAX);
+        assert_eq!(store.mean(i32::MIN, i32::MIN), i32::MAX);
+
+        store.insert(i32::MAX, i32::MIN).unwrap();
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.range(i32::MAX, i32::MAX), (i32::MIN as i128, 1));
+    }
+
+    #[test]
+    fn test_matches_btreemap() {
+        let mut store = PriceStore::new();
+        let mut prices = BTreeMap::new();
+        let mut seed: u64 = 42;
+        let mut next = move || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (seed >> 33) as i32
+        };
+
+        for _ in 0..5000 {
+            let (timestamp, price) = (next() % 2000 - 1000, next() % 20000 - 10000);
+            store.insert(timestamp, price).unwrap();
+            prices.insert(timestamp, price);
+        }
+        assert_eq!(store.len(), prices.len());
+
+        for _ in 0..1000 {
+            let (time_min, time_max) = (next() % 2400 - 1200, next() % 2400 - 1200);
+            assert_eq!(
+                store.mean(time_min, time_max),
+                reference_mean(&prices, time_min, time_max)
+            );
+        }
+    }
+
+    #[test]
+    fn test_sorted_inserts() {
+        /* ascending timestamps are the usual case, the tree has to stay shallow */
+        let mut store = PriceStore::new();
+        for timestamp in 0..200_000 {
+            store.insert(timestamp, timestamp % 100).unwrap();
+        }
+
+        assert_eq!(store.len(), 200_000);
+        assert_eq!(store.mean(100, 199), 49);
+    }
+
+    #[test]
+    fn test_sorted_queries() {
+        /* every aggregate walks the tree recursively somewhere */
+        let mut store = PriceStore::new();
+        for timestamp in 0..100_000 {
+            store.insert(timestamp, timestamp).unwrap();
+        }
+
+        for timestamp in (0..100_000).step_by(997) {
+            assert_eq!(store.get(timestamp), Some(timestamp));
+            assert_eq!(store.mean(timestamp, timestamp + 2), timestamp + 1);
+            assert_eq!(store.min(timestamp, timestamp + 10), Some(timestamp));
+        }
+        assert_eq!(store.mean(0, 99_999), 49_999);
+        assert_eq!(store.max(0, i32::MAX), Some(99_999));
+        assert_eq!(store.median(0, 99_999), Some(49_999));
+        assert_eq!(store.delete(0, 49_999), 50_000);
+        assert_eq!(store.len(), 50_000);
+    }
+
+    #[test]
+    fn test_random_seed() {
+        assert_ne!(PriceStore::new().seed, PriceStore::new().seed);
+    }
+
+    fn store_with(policy: DuplicatePolicy) -> PriceStore {
+        let mut store = PriceStore::new().with_policy(policy);
+        store.insert(10, 100).unwrap();
+        store.insert(20, 200).unwrap();
+        store
+    }
+
+    #[test]
+    fn test_policy_reject() {
+        let mut store = store_with(DuplicatePolicy::Reject);
+
+        assert_eq!(store.insert(10, 50), Err(DuplicateTimestamp(10)));
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(10), Some(100));
+        assert_eq!(store.mean(0, 30), 150);
+    }
+
+    #[test]
+    fn test_policy_keep_first() {
+        let mut store = store_with(DuplicatePolicy::KeepFirst);
+
+        assert_eq!(store.insert(10, 50), Ok(()));
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(10), Some(100));
+    }
+
+    #[test]
+    fn test_policy_keep_last() {
+        let mut store = store_with(DuplicatePolicy::KeepLast);
+
+        assert_eq!(store.insert(10, 50), Ok(()));
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(10), Some(50));
+        assert_eq!(store.mean(0, 30), 125);
+    }
+
+    #[test]
+    fn test_policy_keep_all() {
+        let mut store = store_with(DuplicatePolicy::KeepAll);
+
+        assert_eq!(store.insert(10, 50), Ok(()));
+        assert_eq!(store.insert(10, 30), Ok(()));
+        assert_eq!(store.len(), 4);
+        assert_eq!(store.range(10, 10), (180, 3));
+        assert_eq!(store.mean(10, 10), 60);
+        assert_eq!(store.mean(0, 30), 95);
+    }
+
+    #[test]
+    fn test_mean_rounding() {
+        /* truncated toward zero on both sides */
+        assert_eq!(mean(3, 2), 1);
+        assert_eq!(mean(-3, 2), -1);
+        assert_eq!(mean(-1, 3), 0);
+        assert_eq!(mean(0, 0), 0);
+
+        /* sums far outside of i64 still average correctly */
+        let many = 1usize << 40;
+        assert_eq!(mean(i32::MAX as i128 * many as i128, many), i32::MAX);
+        assert_eq!(mean(i32::MIN as i128 * many as i128, many), i32::MIN);
+
+        /* cannot come from i32 prices, but stays in range regardless */
+        assert_eq!(mean(i128::MAX, 1), i32::MAX);
+        assert_eq!(mean(i128::MIN, 1), i32::MIN);
+    }
+
+    #[test]
+    fn test_aggregates() {
+        let mut store = PriceStore::new();
+        for (timestamp, price) in [(1, 5), (2, -3), (3, 8), (4, 1), (10, 100)] {
+            store.insert(timestamp, price).unwrap();
+        }
+
+        assert_eq!(store.min(1, 4), Some(-3));
+        assert_eq!(store.max(1, 4), Some(8));
+        assert_eq!(store.median(1, 3), Some(5));
+        /* -3 1 5 8, (1 + 5) / 2 */
+        assert_eq!(store.median(1, 4), Some(3));
+        assert_eq!(store.median(2, 2), Some(-3));
+
+        assert_eq!(store.min(5, 9), None);
+        assert_eq!(store.max(4, 1), None);
+        assert_eq!(store.median(11, 20), None);
+
+        /* the queries put the tree back together */
+        assert_eq!(store.len(), 5);
+        assert_eq!(store.range(i32::MIN, i32::MAX), (111, 5));
+    }
+
+    #[test]
+    fn test_median_keep_all() {
+        let mut store = PriceStore::new().with_policy(DuplicatePolicy::KeepAll);
+        for price in [i32::MAX, i32::MAX - 2, 7] {
+            store.insert(0, price).unwrap();
+        }
+
+        assert_eq!(store.median(0, 0), Some(i32::MAX - 2));
+        store.insert(0, i32::MAX).unwrap();
+        assert_eq!(store.median(0, 0), Some(i32::MAX - 1));
+    }
+
+    #[test]
+    fn test_delete() {
+        let mut store = PriceStore::new();
+        for timestamp in 0..100 {
+            store.insert(timestamp, timestamp).unwrap();
+        }
+
+        assert_eq!(store.delete(10, 89), 80);
+        assert_eq!(store.delete(10, 89), 0);
+        assert_eq!(store.delete(50, 0), 0);
+
+        assert_eq!(store.len(), 20);
+        assert_eq!(store.range(0, 99), ((0..10).chain(90..100).sum(), 20));
+        assert_eq!(store.max(0, 50), Some(9));
+        assert_eq!(store.min(50, 99), Some(90));
+
+        assert_eq!(store.delete(i32::MIN, i32::MAX), 20);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_aggregates_match_btreemap() {
+        let mut store = PriceStore::new();
+        let mut prices = BTreeMap::new();
+        let mut seed: u64 = 7;
+        let mut next = move || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (seed >> 33) as i32
+        };
+
+        for _ in 0..3000 {
+            let (timestamp, price) = (next() % 2000 - 1000, next() % 20000 - 10000);
+            store.insert(timestamp, price).unwrap();
+            prices.insert(timestamp, price);
+        }
+
+        for _ in 0..300 {
+            let (time_min, time_max) = (next() % 2400 - 1200, next() % 2400 - 1200);
+            let mut inside: Vec<i32> = if time_min <= time_max {
+                prices.range(time_min..=time_max).map(|(_, p)| *p).collect()
+            } else {
+                vec![]
+            };
+            inside.sort();
+
+            assert_eq!(store.min(time_min, time_max), inside.first().copied());
+            assert_eq!(store.max(time_min, time_max), inside.last().copied());
+
+            let median = match inside.len() {
+                0 => None,
+                n if n % 2 == 1 => Some(inside[n / 2]),
+                n => Some(mean(inside[n / 2 - 1] as i128 + inside[n / 2] as i128, 2)),
+            };
+            assert_eq!(store.median(time_min, time_max), median);
+        }
+    }
+}
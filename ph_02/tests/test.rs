@@ -0,0 +1,350 @@
+use futures::sink::SinkExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout, Duration};
+use tokio_stream::StreamExt;
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+use protohackers::codec::{Aggregate, MeansCodec, Msg};
+use protohackers::server::MeansServer;
+use protohackers::store::DuplicatePolicy;
+
+async fn spawn_app(port: u16, server: MeansServer) -> String {
+    let hostname = format!("127.0.0.1:{port}");
+    let listen = hostname.clone();
+    tokio::spawn(async move {
+        let _ = server.run(listen).await;
+    });
+    sleep(Duration::from_millis(100)).await;
+
+    hostname
+}
+
+#[tokio::test]
+async fn test_example_session() {
+    let hostname = spawn_app(7777, MeansServer::new()).await;
+
+    let stream = TcpStream::connect(&hostname).await.unwrap();
+    let (mut reader, writer) = stream.into_split();
+    let mut writer = FramedWrite::new(writer, MeansCodec::new());
+
+    for (timestamp, price) in [(12345, 101), (12346, 102), (12347, 100), (40960, 5)] {
+        writer.send(Msg::Insert { timestamp, price }).await.unwrap();
+    }
+    writer
+        .send(Msg::Query {
+            time_min: 12288,
+            time_max: 16384,
+        })
+        .await
+        .unwrap();
+
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf).await.unwrap();
+    assert_eq!(i32::from_be_bytes(buf), 101);
+}
+
+#[tokio::test]
+async fn test_byte_by_byte() {
+    let hostname = spawn_app(7778, MeansServer::new()).await;
+
+    let mut stream = TcpStream::connect(&hostname).await.unwrap();
+    let data = b"\x49\x00\x00\x00\x01\x00\x00\x00\x0a\x49\x00\x00\x00\x02\x00\x00\x00\x0f\x51\x00\x00\x00\x00\x00\x00\x00\x05";
+
+    for byte in data {
+        stream.write_all(&[*byte]).await.unwrap();
+        stream.flush().await.unwrap();
+        sleep(Duration::from_millis(1)).await;
+    }
+
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(i32::from_be_bytes(buf), 12);
+}
+
+#[tokio::test]
+async fn test_sessions_are_separate() {
+    let hostname = spawn_app(7779, MeansServer::new()).await;
+
+    let mut first = TcpStream::connect(&hostname).await.unwrap();
+    first
+        .write_all(b"\x49\x00\x00\x00\x01\x00\x00\x00\x64")
+        .await
+        .unwrap();
+
+    let stream = TcpStream::connect(&hostname).await.unwrap();
+    let (mut reader, writer) = stream.into_split();
+    let mut writer = FramedWrite::new(writer, MeansCodec::new());
+    writer
+        .send(Msg::Query {
+            time_min: i32::MIN,
+            time_max: i32::MAX,
+        })
+        .await
+        .unwrap();
+
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf).await.unwrap();
+    assert_eq!(i32::from_be_bytes(buf), 0);
+}
+
+#[tokio::test]
+async fn test_unknown_type_disconnects() {
+    let hostname = spawn_app(7780, MeansServer::new()).await;
+
+    let mut stream = TcpStream::connect(&hostname).await.unwrap();
+    stream
+        .write_all(b"\x58\x00\x00\x00\x01\x00\x00\x00\x02")
+        .await
+        .unwrap();
+
+    let mut reader = FramedRead::new(stream, MeansCodec::new());
+    let next = timeout(Duration::from_secs(5), reader.next())
+        .await
+        .expect("Server did not close the connection");
+    assert!(next.is_none());
+}
+
+#[tokio::test]
+async fn test_duplicate_policies() {
+    let policies = [
+        (DuplicatePolicy::KeepFirst, 10),
+        (DuplicatePolicy::KeepLast, 30),
+        (DuplicatePolicy::KeepAll, 20),
+    ];
+
+    for (port, (policy, expected)) in (7781..).zip(policies) {
+        let hostname = spawn_app(port, MeansServer::new().with_duplicates(policy)).await;
+
+        let stream = TcpStream::connect(&hostname).await.unwrap();
+        let (mut reader, writer) = stream.into_split();
+        let mut writer = FramedWrite::new(writer, MeansCodec::new());
+
+        for price in [10, 30] {
+            let insert = Msg::Insert {
+                timestamp: 5,
+                price,
+            };
+            writer.send(insert).await.unwrap();
+        }
+        let query = Msg::Query {
+            time_min: 0,
+            time_max: 10,
+        };
+        writer.send(query).await.unwrap();
+
+        let mut buf = [0u8; 4];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(i32::from_be_bytes(buf), expected, "{policy:?}");
+    }
+}
+
+#[tokio::test]
+async fn test_duplicate_rejected() {
+    let server = MeansServer::new().with_duplicates(DuplicatePolicy::Reject);
+    let hostname = spawn_app(7784, server).await;
+
+    let mut stream = TcpStream::connect(&hostname).await.unwrap();
+    stream
+        .write_all(b"\x49\x00\x00\x00\x05\x00\x00\x00\x0a\x49\x00\x00\x00\x05\x00\x00\x00\x1e")
+        .await
+        .unwrap();
+
+    let mut reader = FramedRead::new(stream, MeansCodec::new());
+    let next = timeout(Duration::from_secs(5), reader.next())
+        .await
+        .expect("Server did not close the connection");
+    assert!(next.is_none());
+}
+
+async fn query_all(writer: &mut FramedWrite<OwnedWriteHalf, MeansCodec>) {
+    let query = Msg::Query {
+        time_min: i32::MIN,
+        time_max: i32::MAX,
+    };
+    writer.send(query).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_persistent_session() {
+    let tmp = tempfile::tempdir().unwrap();
+    let server = MeansServer::new().with_session_dir(Some(tmp.path().to_path_buf()));
+    let hostname = spawn_app(7785, server).await;
+
+    for (price, expected) in [(100, 100), (200, 150)] {
+        let stream = TcpStream::connect(&hostname).await.unwrap();
+        let (mut reader, writer) = stream.into_split();
+        let mut writer = FramedWrite::new(writer, MeansCodec::new());
+
+        writer
+            .send(Msg::Session {
+                name: *b"alice\0\0\0",
+            })
+            .await
+            .unwrap();
+        let insert = Msg::Insert {
+            timestamp: price,
+            price,
+        };
+        writer.send(insert).await.unwrap();
+        query_all(&mut writer).await;
+
+        let mut buf = [0u8; 4];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(i32::from_be_bytes(buf), expected);
+    }
+
+    /* clients without a session still start out empty */
+    let stream = TcpStream::connect(&hostname).await.unwrap();
+    let (mut reader, writer) = stream.into_split();
+    let mut writer = FramedWrite::new(writer, MeansCodec::new());
+    query_all(&mut writer).await;
+
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf).await.unwrap();
+    assert_eq!(i32::from_be_bytes(buf), 0);
+}
+
+#[tokio::test]
+async fn test_session_in_use() {
+    let tmp = tempfile::tempdir().unwrap();
+    let server = MeansServer::new().with_session_dir(Some(tmp.path().to_path_buf()));
+    let hostname = spawn_app(7786, server).await;
+
+    let mut first = TcpStream::connect(&hostname).await.unwrap();
+    first.write_all(b"Sbob\0\0\0\0\0").await.unwrap();
+    sleep(Duration::from_millis(100)).await;
+
+    let mut second = TcpStream::connect(&hostname).await.unwrap();
+    second.write_all(b"Sbob\0\0\0\0\0").await.unwrap();
+
+    let mut reader = FramedRead::new(second, MeansCodec::new());
+    let next = timeout(Duration::from_secs(5), reader.next())
+        .await
+        .expect("Server did not close the connection");
+    assert!(next.is_none());
+}
+
+#[tokio::test]
+async fn test_session_disabled_by_default() {
+    let hostname = spawn_app(7787, MeansServer::new()).await;
+
+    let mut stream = TcpStream::connect(&hostname).await.unwrap();
+    stream.write_all(b"Salice\0\0\0").await.unwrap();
+
+    let mut reader = FramedRead::new(stream, MeansCodec::new());
+    let next = timeout(Duration::from_secs(5), reader.next())
+        .await
+        .expect("Server did not close the connection");
+    assert!(next.is_none());
+}
+
+#[tokio::test]
+async fn test_extensions() {
+    let tmp = tempfile::tempdir().unwrap();
+    let server = MeansServer::new()
+        .with_extensions(true)
+        .with_session_dir(Some(tmp.path().to_path_buf()));
+    let hostname = spawn_app(7788, server).await;
+
+    let stream = TcpStream::connect(&hostname).await.unwrap();
+    let (mut reader, writer) = stream.into_split();
+    let mut writer = FramedWrite::new(writer, MeansCodec::new());
+
+    writer
+        .send(Msg::Session {
+            name: *b"carol\0\0\0",
+        })
+        .await
+        .unwrap();
+    for (timestamp, price) in [(1, 10), (2, 40), (3, 20), (4, 2147483647), (5, 30)] {
+        writer.send(Msg::Insert { timestamp, price }).await.unwrap();
+    }
+    writer
+        .send(Msg::Delete {
+            time_min: 4,
+            time_max: 4,
+        })
+        .await
+        .unwrap();
+
+    let aggregates = [
+        (Aggregate::Min, 10),
+        (Aggregate::Max, 40),
+        (Aggregate::Count, 4),
+        (Aggregate::Median, 25),
+    ];
+    for (aggregate, expected) in aggregates {
+        let msg = Msg::Aggregate {
+            aggregate,
+            time_min: 0,
+            time_max: 10,
+        };
+        writer.send(msg).await.unwrap();
+
+        let mut buf = [0u8; 4];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(i32::from_be_bytes(buf), expected, "{aggregate:?}");
+    }
+
+    let msg = Msg::Aggregate {
+        aggregate: Aggregate::Sum,
+        time_min: 0,
+        time_max: 10,
+    };
+    writer.send(msg).await.unwrap();
+
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf).await.unwrap();
+    assert_eq!(i64::from_be_bytes(buf), 100);
+
+    /* the delete is persisted along with the inserts */
+    drop(writer);
+    drop(reader);
+    sleep(Duration::from_millis(100)).await;
+
+    let stream = TcpStream::connect(&hostname).await.unwrap();
+    let (mut reader, writer) = stream.into_split();
+    let mut writer = FramedWrite::new(writer, MeansCodec::new());
+    writer
+        .send(Msg::Session {
+            name: *b"carol\0\0\0",
+        })
+        .await
+        .unwrap();
+    query_all(&mut writer).await;
+
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf).await.unwrap();
+    assert_eq!(i32::from_be_bytes(buf), 25);
+}
+
+#[tokio::test]
+async fn test_extensions_disabled_by_default() {
+    let hostname = spawn_app(7789, MeansServer::new()).await;
+
+    let stream = TcpStream::connect(&hostname).await.unwrap();
+    let mut writer = FramedWrite::new(stream, MeansCodec::new());
+    writer
+        .send(Msg::Insert {
+            timestamp: 1,
+            price: 1,
+        })
+        .await
+        .unwrap();
+    writer
+        .send(Msg::Aggregate {
+            aggregate: Aggregate::Max,
+            time_min: 0,
+            time_max: 10,
+        })
+        .await
+        .unwrap();
+
+    let mut reader = FramedRead::new(writer.into_inner(), MeansCodec::new());
+    let next = timeout(Duration::from_secs(5), reader.next())
+        .await
+        .expect("Server did not close the connection");
+    assert!(next.is_none());
+}
@@ -0,0 +1,376 @@
+use bytes::{Buf, BufMut, BytesMut};
+use std::io;
+use tokio_util::codec::{Decoder, Encoder};
+
+pub const DEFAULT_MAX_LINE_LEN: usize = 2000;
+pub const DEFAULT_MAX_VIOLATIONS: u32 = 3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LongLines {
+    /// Keep the start of the line up to the limit
+    #[default]
+    Truncate,
+    /// Ignore the whole line
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum InvalidUtf8 {
+    /// Replace the offending bytes with U+FFFD
+    #[default]
+    Lossy,
+    /// Ignore the whole line
+    Reject,
+}
+
+/* how input that is not a proper line is dealt with */
+#[derive(Debug, Clone)]
+pub struct LinePolicy {
+    /* in bytes, not counting the newline */
+    pub max_len: usize,
+    pub long_lines: LongLines,
+    pub invalid_utf8: InvalidUtf8,
+    /* a client is disconnected on this many, 0 never */
+    pub max_violations: u32,
+}
+
+impl LinePolicy {
+    /* what the client is told about a line that was not taken as is */
+    pub fn notice(&self, input: &Input) -> Option<String> {
+        let max_len = self.max_len;
+        let notice = match input {
+            Input::Line(_) => return None,
+            Input::Repaired(_, Violation::TooLong) => {
+                format!("* Line truncated to {max_len} bytes")
+            }
+            Input::Rejected(Violation::TooLong) => {
+                format!("* Line longer than {max_len} bytes ignored")
+            }
+            Input::Repaired(_, Violation::InvalidUtf8) => {
+                "* Invalid UTF-8 in line replaced".to_string()
+            }
+            Input::Rejected(Violation::InvalidUtf8) => {
+                "* Line with invalid UTF-8 ignored".to_string()
+            }
+        };
+
+        Some(notice)
+    }
+}
+
+impl Default for LinePolicy {
+    fn default() -> Self {
+        LinePolicy {
+            max_len: DEFAULT_MAX_LINE_LEN,
+            long_lines: LongLines::default(),
+            invalid_utf8: InvalidUtf8::default(),
+            max_violations: DEFAULT_MAX_VIOLATIONS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    TooLong,
+    InvalidUtf8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    Line(String),
+    /* made into a line according to the policy */
+    Repaired(String, Violation),
+    Rejected(Violation),
+}
+
+impl Input {
+    pub fn violation(&self) -> Option<Violation> {
+        match self {
+            Input::Line(_) => None,
+            Input::Repaired(_, violation) | Input::Rejected(violation) => Some(*violation),
+        }
+    }
+
+    pub fn into_line(self) -> Option<String> {
+        match self {
+            Input::Line(line) | Input::Repaired(line, _) => Some(line),
+            Input::Rejected(_) => None,
+        }
+    }
+}
+
+/*
+ * Newline separated lines like LinesCodec, but an over-long line or one that
+ * is not UTF-8 is handed out according to the policy instead of failing the
+ * stream. Past the length limit, input is dropped until the next newline so
+ * the line boundaries stay intact.
+ */
+#[derive(Debug)]
+pub struct ChatCodec {
+    policy: LinePolicy,
+    /* where to continue looking for a newline */
+    next_index: usize,
+    /* the start of the current line once it went over the limit */
+    overlong: Option<BytesMut>,
+}
+
+impl ChatCodec {
+    pub fn new(policy: LinePolicy) -> ChatCodec {
+        ChatCodec {
+            policy,
+            next_index: 0,
+            overlong: None,
+        }
+    }
+
+    fn finish(&self, mut line: BytesMut, too_long: bool) -> Input {
+        if too_long && self.policy.long_lines == LongLines::Reject {
+            return Input::Rejected(Violation::TooLong);
+        }
+        if line.last() == Some(&b'\r') {
+            line.truncate(line.len() - 1);
+        }
+
+        let text = match std::str::from_utf8(&line) {
+            Ok(text) => text.to_string(),
+            /* cut in the middle of a character by the truncation */
+            Err(e) if too_long && e.error_len().is_none() => {
+                String::from_utf8_lossy(&line[..e.valid_up_to()]).to_string()
+            }
+            Err(_) => match self.policy.invalid_utf8 {
+                InvalidUtf8::Reject => return Input::Rejected(Violation::InvalidUtf8),
+                InvalidUtf8::Lossy if too_long => String::from_utf8_lossy(&line).to_string(),
+                InvalidUtf8::Lossy => {
+                    let text = String::from_utf8_lossy(&line).to_string();
+                    return Input::Repaired(text, Violation::InvalidUtf8);
+                }
+            },
+        };
+
+        if too_long {
+            Input::Repaired(text, Violation::TooLong)
+        } else {
+            Input::Line(text)
+        }
+    }
+}
+
+impl Default for ChatCodec {
+    fn default() -> Self {
+        ChatCodec::new(LinePolicy::default())
+    }
+}
+
+impl Decoder for ChatCodec {
+    type Item = Input;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Input>, io::Error> {
+        let max_len = self.policy.max_len;
+        let newline = buf[self.next_index..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .map(|i| i + self.next_index);
+
+        match (self.overlong.take(), newline) {
+            (Some(head), Some(i)) => {
+                buf.advance(i + 1);
+                self.next_index = 0;
+                Ok(Some(self.finish(head, true)))
+            }
+            (Some(head), None) => {
+                buf.clear();
+                self.next_index = 0;
+                self.overlong = Some(head);
+                Ok(None)
+            }
+            (None, Some(i)) => {
+                let mut line = buf.split_to(i + 1);
+                line.truncate(i);
+                self.next_index = 0;
+
+                /* a carriage return before the newline does not count */
+                let len = line.len() - usize::from(line.last() == Some(&b'\r'));
+                if len > max_len {
+                    line.truncate(max_len);
+                    return Ok(Some(self.finish(line, true)));
+                }
+                Ok(Some(self.finish(line, false)))
+            }
+            /* one more byte could still be the carriage return */
+            (None, None) if buf.len() > max_len + 1 => {
+                self.overlong = Some(buf.split_to(max_len));
+                buf.clear();
+                self.next_index = 0;
+                Ok(None)
+            }
+            (None, None) => {
+                self.next_index = buf.len();
+                Ok(None)
+            }
+        }
+    }
+
+    /* like LinesCodec, a last line without a newline still counts */
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Input>, io::Error> {
+        if let Some(input) = self.decode(buf)? {
+            return Ok(Some(input));
+        }
+        if let Some(head) = self.overlong.take() {
+            return Ok(Some(self.finish(head, true)));
+        }
+        if buf.is_empty() {
+            return Ok(None);
+        }
+
+        let line = buf.split();
+        self.next_index = 0;
+        Ok(Some(self.finish(line, false)))
+    }
+}
+
+impl<T: AsRef<str>> Encoder<T> for ChatCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, line: T, buf: &mut BytesMut) -> Result<(), io::Error> {
+        let line = line.as_ref();
+        buf.reserve(line.len() + 1);
+        buf.put(line.as_bytes());
+        buf.put_u8(b'\n');
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_policy(max_len: usize, long_lines: LongLines, invalid_utf8: InvalidUtf8) -> ChatCodec {
+        ChatCodec::new(LinePolicy {
+            max_len,
+            long_lines,
+            invalid_utf8,
+            ..Default::default()
+        })
+    }
+
+    fn decode_all(codec: &mut ChatCodec, data: &[u8]) -> Vec<Input> {
+        let mut buf = BytesMut::from(data);
+        let mut inputs = Vec::new();
+        while let Some(input) = codec.decode(&mut buf).unwrap() {
+            inputs.push(input);
+        }
+        inputs
+    }
+
+    fn line(text: &str) -> Input {
+        Input::Line(text.to_string())
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut codec = ChatCodec::default();
+        assert_eq!(
+            decode_all(&mut codec, b"hello\nworld\r\n\npartial"),
+            vec![line("hello"), line("world"), line("")]
+        );
+    }
+
+    #[test]
+    fn test_truncate() {
+        let mut codec = with_policy(5, LongLines::Truncate, InvalidUtf8::Lossy);
+        assert_eq!(
+            decode_all(&mut codec, b"abcdefgh\nabcde\r\nok\n"),
+            vec![
+                Input::Repaired("abcde".to_string(), Violation::TooLong),
+                line("abcde"),
+                line("ok")
+            ]
+        );
+
+        /* no half characters from cutting a multi-byte one */
+        assert_eq!(
+            decode_all(&mut codec, "abcd\u{e9}\n".as_bytes()),
+            vec![Input::Repaired("abcd".to_string(), Violation::TooLong)]
+        );
+    }
+
+    #[test]
+    fn test_reject_long() {
+        let mut codec = with_policy(5, LongLines::Reject, InvalidUtf8::Lossy);
+        assert_eq!(
+            decode_all(&mut codec, b"abcdefgh\nok\n"),
+            vec![Input::Rejected(Violation::TooLong), line("ok")]
+        );
+    }
+
+    #[test]
+    fn test_long_line_in_pieces() {
+        let mut codec = with_policy(4, LongLines::Truncate, InvalidUtf8::Lossy);
+        let mut buf = BytesMut::new();
+
+        /* the buffer does not grow past the limit while waiting for the newline */
+        for _ in 0..1000 {
+            buf.put(&b"xyz"[..]);
+            assert_eq!(codec.decode(&mut buf).unwrap(), None);
+            assert!(buf.len() <= 4 + 1);
+        }
+        buf.put(&b"\nnext\n"[..]);
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Input::Repaired("xyzx".to_string(), Violation::TooLong))
+        );
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(line("next")));
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let mut codec = with_policy(100, LongLines::Truncate, InvalidUtf8::Lossy);
+        assert_eq!(
+            decode_all(&mut codec, b"caf\xe9\nok\n"),
+            vec![
+                Input::Repaired("caf\u{fffd}".to_string(), Violation::InvalidUtf8),
+                line("ok")
+            ]
+        );
+
+        let mut codec = with_policy(100, LongLines::Truncate, InvalidUtf8::Reject);
+        assert_eq!(
+            decode_all(&mut codec, b"caf\xe9\nok\n"),
+            vec![Input::Rejected(Violation::InvalidUtf8), line("ok")]
+        );
+    }
+
+    #[test]
+    fn test_eof() {
+        let mut codec = ChatCodec::default();
+        let mut buf = BytesMut::from(&b"one\nlast"[..]);
+
+        assert_eq!(codec.decode_eof(&mut buf).unwrap(), Some(line("one")));
+        assert_eq!(codec.decode_eof(&mut buf).unwrap(), Some(line("last")));
+        assert_eq!(codec.decode_eof(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn test_notice() {
+        let policy = LinePolicy {
+            max_len: 5,
+            ..Default::default()
+        };
+
+        assert_eq!(policy.notice(&line("hi")), None);
+        assert_eq!(
+            policy
+                .notice(&Input::Repaired("abcde".to_string(), Violation::TooLong))
+                .unwrap(),
+            "* Line truncated to 5 bytes"
+        );
+        assert_eq!(
+            policy
+                .notice(&Input::Rejected(Violation::InvalidUtf8))
+                .unwrap(),
+            "* Line with invalid UTF-8 ignored"
+        );
+    }
+}
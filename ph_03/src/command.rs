@@ -0,0 +1,166 @@
+use crate::moderation::MAX_MUTE;
+
+pub const MUTE_USAGE: &str = "/mute <user> [seconds]";
+
+/*
+ * With commands enabled, lines starting with one of the known commands are
+ * handled by the server, everything else, including unknown `/words`, is chat
+ * as before.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /* move to another room, creating it if nobody is there yet */
+    Join(String),
+    /* back to the default room */
+    Leave,
+    Rooms,
+    Who,
+    /* private message to a user in any room */
+    Msg { to: String, text: String },
+    Me(String),
+    Nick(String),
+    Quit,
+    /* admins only, disconnect a user */
+    Kick(String),
+    /* admins only, silence a user for a while, the default mute if no seconds */
+    Mute { user: String, seconds: Option<u64> },
+    Unmute(String),
+    /* a known command with arguments it cannot take, answered with its usage */
+    Usage(&'static str),
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Option<Command> {
+        let (word, arg) = match line.split_once(' ') {
+            Some((word, arg)) => (word, arg.trim()),
+            None => (line, ""),
+        };
+
+        match word {
+            "/join" => Some(Command::Join(arg.to_string())),
+            "/leave" if arg.is_empty() => Some(Command::Leave),
+            "/rooms" if arg.is_empty() => Some(Command::Rooms),
+            "/who" if arg.is_empty() => Some(Command::Who),
+            "/msg" => {
+                let (to, text) = arg.split_once(' ').unwrap_or((arg, ""));
+                Some(Command::Msg {
+                    to: to.to_string(),
+                    text: text.trim().to_string(),
+                })
+            }
+            "/me" => Some(Command::Me(arg.to_string())),
+            "/nick" => Some(Command::Nick(arg.to_string())),
+            "/quit" if arg.is_empty() => Some(Command::Quit),
+            "/kick" => Some(Command::Kick(arg.to_string())),
+            "/mute" => {
+                let (user, seconds) = arg.split_once(' ').unwrap_or((arg, ""));
+                let seconds = match seconds.trim() {
+                    "" => None,
+                    seconds => match seconds.parse().ok().filter(|s| *s <= MAX_MUTE.as_secs()) {
+                        Some(seconds) => Some(seconds),
+                        None => return Some(Command::Usage(MUTE_USAGE)),
+                    },
+                };
+                Some(Command::Mute {
+                    user: user.to_string(),
+                    seconds,
+                })
+            }
+            "/unmute" => Some(Command::Unmute(arg.to_string())),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Command::parse("/join rust"),
+            Some(Command::Join("rust".to_string()))
+        );
+        assert_eq!(Command::parse("/join"), Some(Command::Join(String::new())));
+        assert_eq!(Command::parse("/leave"), Some(Command::Leave));
+        assert_eq!(Command::parse("/rooms"), Some(Command::Rooms));
+        assert_eq!(Command::parse("/who"), Some(Command::Who));
+        assert_eq!(
+            Command::parse("/msg bob see you  at 5"),
+            Some(Command::Msg {
+                to: "bob".to_string(),
+                text: "see you  at 5".to_string()
+            })
+        );
+        assert_eq!(
+            Command::parse("/msg bob"),
+            Some(Command::Msg {
+                to: "bob".to_string(),
+                text: String::new()
+            })
+        );
+        assert_eq!(
+            Command::parse("/me waves"),
+            Some(Command::Me("waves".to_string()))
+        );
+        assert_eq!(
+            Command::parse("/nick bobby"),
+            Some(Command::Nick("bobby".to_string()))
+        );
+        assert_eq!(Command::parse("/quit"), Some(Command::Quit));
+    }
+
+    #[test]
+    fn test_parse_moderation() {
+        assert_eq!(
+            Command::parse("/kick bob"),
+            Some(Command::Kick("bob".to_string()))
+        );
+        assert_eq!(
+            Command::parse("/mute bob"),
+            Some(Command::Mute {
+                user: "bob".to_string(),
+                seconds: None
+            })
+        );
+        assert_eq!(
+            Command::parse("/mute bob 30"),
+            Some(Command::Mute {
+                user: "bob".to_string(),
+                seconds: Some(30)
+            })
+        );
+        assert_eq!(
+            Command::parse("/mute bob soon"),
+            Some(Command::Usage(MUTE_USAGE))
+        );
+        assert_eq!(
+            Command::parse("/mute bob 86400"),
+            Some(Command::Mute {
+                user: "bob".to_string(),
+                seconds: Some(86400)
+            })
+        );
+        for seconds in ["86401", "18446744073709551615", "18446744073709551616"] {
+            assert_eq!(
+                Command::parse(&format!("/mute bob {seconds}")),
+                Some(Command::Usage(MUTE_USAGE))
+            );
+        }
+        assert_eq!(
+            Command::parse("/unmute bob"),
+            Some(Command::Unmute("bob".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_chat() {
+        assert_eq!(Command::parse("hello"), None);
+        assert_eq!(Command::parse("/shrug"), None);
+        assert_eq!(Command::parse("/who is there?"), None);
+        assert_eq!(Command::parse(" /rooms"), None);
+        assert_eq!(Command::parse("/quit now"), None);
+        assert_eq!(Command::parse(""), None);
+    }
+}
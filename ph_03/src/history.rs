@@ -0,0 +1,326 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::PathBuf;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+const EXTENSION: &str = "history";
+/* rooms come and go, past this many open files they are all closed */
+const MAX_OPEN_FILES: usize = 64;
+/* writes waiting for the disk, any more are dropped and counted */
+const WRITE_QUEUE_LEN: usize = 1024;
+
+#[derive(Debug, Default)]
+struct Room {
+    lines: VecDeque<String>,
+    /* lines appended to the file since it was last rewritten */
+    appended: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Write {
+    Append { room: String, line: String },
+    Rewrite { room: String, lines: Vec<String> },
+}
+
+/*
+ * The last chat lines of every room, replayed to whoever joins it. With a
+ * directory, each room also keeps an append-only `{room}.history` file which
+ * is rewritten to just the buffered lines whenever it grew to twice that, so
+ * it never holds more than 2 * depth lines. The files are written by a task
+ * of their own, in the order the lines were pushed, so the room never waits
+ * on the disk. When the disk falls that far behind, writes are dropped and
+ * the room's file is rewritten in full on its next line.
+ */
+#[derive(Debug, Default)]
+pub struct History {
+    depth: usize,
+    rooms: HashMap<String, Room>,
+    tx: Option<mpsc::Sender<Write>>,
+    writer: Option<JoinHandle<()>>,
+    dropped: u64,
+}
+
+impl History {
+    pub fn new(depth: usize) -> History {
+        History {
+            depth,
+            ..Default::default()
+        }
+    }
+
+    /* picks up whatever an earlier run left in the directory */
+    pub async fn persistent(depth: usize, dir: impl Into<PathBuf>) -> io::Result<History> {
+        let mut files = Files::new(dir.into());
+        tokio::fs::create_dir_all(&files.dir).await?;
+
+        let mut rooms = HashMap::new();
+        let mut entries = tokio::fs::read_dir(&files.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != EXTENSION) {
+                continue;
+            }
+            let Some(room) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+
+            let data = tokio::fs::read_to_string(&path).await?;
+            let skip = data.lines().count().saturating_sub(depth);
+            let lines: VecDeque<String> = data.lines().skip(skip).map(String::from).collect();
+            files.rewrite(room, lines.iter().cloned().collect()).await?;
+            rooms.insert(room.to_string(), Room { lines, appended: 0 });
+        }
+
+        let (tx, mut rx) = mpsc::channel(WRITE_QUEUE_LEN);
+        let writer = tokio::spawn(async move {
+            while let Some(write) = rx.recv().await {
+                if let Err(e) = files.write(write).await {
+                    println!("Failed to save history: {e:?}");
+                }
+            }
+        });
+
+        Ok(History {
+            depth,
+            rooms,
+            tx: Some(tx),
+            writer: Some(writer),
+            dropped: 0,
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.depth > 0
+    }
+
+    /* oldest first */
+    pub fn lines(&self, room: &str) -> Vec<String> {
+        self.rooms
+            .get(room)
+            .map(|room| room.lines.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn push(&mut self, room: &str, line: &str) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let depth = self.depth;
+        let entry = self.rooms.entry(room.to_string()).or_default();
+        if entry.lines.len() >= depth {
+            entry.lines.pop_front();
+        }
+        entry.lines.push_back(line.to_string());
+        entry.appended += 1;
+
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        let write = if entry.appended >= depth {
+            entry.appended = 0;
+            Write::Rewrite {
+                room: room.to_string(),
+                lines: entry.lines.iter().cloned().collect(),
+            }
+        } else {
+            Write::Append {
+                room: room.to_string(),
+                line: line.to_string(),
+            }
+        };
+        if tx.try_send(write).is_err() {
+            entry.appended = depth;
+            self.dropped += 1;
+            if self.dropped.is_power_of_two() {
+                println!("History writes falling behind, {} dropped", self.dropped);
+            }
+        }
+    }
+
+    /* writes dropped because the writer task fell behind */
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /* waits for everything pushed so far to be written */
+    pub async fn close(mut self) {
+        self.tx = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.await;
+        }
+    }
+}
+
+/* the history directory, only touched by the writer task */
+#[derive(Debug)]
+struct Files {
+    dir: PathBuf,
+    open: HashMap<String, File>,
+}
+
+impl Files {
+    fn new(dir: PathBuf) -> Files {
+        Files {
+            dir,
+            open: HashMap::new(),
+        }
+    }
+
+    fn path(&self, room: &str) -> PathBuf {
+        self.dir.join(format!("{room}.{EXTENSION}"))
+    }
+
+    async fn write(&mut self, write: Write) -> io::Result<()> {
+        match write {
+            Write::Append { room, line } => self.append(&room, &line).await,
+            Write::Rewrite { room, lines } => self.rewrite(&room, lines).await,
+        }
+    }
+
+    async fn append(&mut self, room: &str, line: &str) -> io::Result<()> {
+        if !self.open.contains_key(room) {
+            if self.open.len() >= MAX_OPEN_FILES {
+                self.open.clear();
+            }
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.path(room))
+                .await?;
+            self.open.insert(room.to_string(), file);
+        }
+
+        let file = self.open.get_mut(room).expect("opened above");
+        file.write_all(format!("{line}\n").as_bytes()).await?;
+        file.flush().await
+    }
+
+    /* replaces the file with the buffered lines, via a rename so a crash cannot lose both */
+    async fn rewrite(&mut self, room: &str, lines: Vec<String>) -> io::Result<()> {
+        /* the handle would still point at the replaced file */
+        self.open.remove(room);
+
+        let path = self.path(room);
+        let data: String = lines.iter().map(|line| format!("{line}\n")).collect();
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, data).await?;
+        tokio::fs::rename(&tmp, &path).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_ring() {
+        let mut history = History::new(3);
+        for n in 0..5 {
+            history.push("lobby", &n.to_string());
+        }
+        history.push("rust", "hi");
+
+        assert_eq!(history.lines("lobby"), vec!["2", "3", "4"]);
+        assert_eq!(history.lines("rust"), vec!["hi"]);
+        assert!(history.lines("empty").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_append_open_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut files = Files::new(dir.path().to_path_buf());
+        let read = || std::fs::read_to_string(dir.path().join("lobby.history")).unwrap();
+
+        /* each line is on disk once written, with the file still open */
+        files.append("lobby", "one").await.unwrap();
+        assert_eq!(read(), "one\n");
+        files.append("lobby", "two").await.unwrap();
+        assert_eq!(read(), "one\ntwo\n");
+
+        /* after a rewrite appends go to the new file */
+        files
+            .rewrite("lobby", vec!["two".to_string()])
+            .await
+            .unwrap();
+        files.append("lobby", "three").await.unwrap();
+        assert_eq!(read(), "two\nthree\n");
+    }
+
+    #[tokio::test]
+    async fn test_writes_dropped() {
+        let (tx, mut rx) = mpsc::channel(2);
+        let mut history = History {
+            tx: Some(tx),
+            ..History::new(10)
+        };
+        let append = |line: &str| Write::Append {
+            room: "lobby".to_string(),
+            line: line.to_string(),
+        };
+
+        /* nothing is writing, the third line does not fit */
+        for line in ["one", "two", "three"] {
+            history.push("lobby", line);
+        }
+        assert_eq!(history.dropped(), 1);
+        assert_eq!(rx.recv().await, Some(append("one")));
+        assert_eq!(rx.recv().await, Some(append("two")));
+
+        /* so the next line rewrites the file with what it missed */
+        history.push("lobby", "four");
+        assert_eq!(
+            rx.recv().await,
+            Some(Write::Rewrite {
+                room: "lobby".to_string(),
+                lines: vec!["one", "two", "three", "four"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect()
+            })
+        );
+        history.push("lobby", "five");
+        assert_eq!(rx.recv().await, Some(append("five")));
+        assert_eq!(history.lines("lobby").len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_disabled() {
+        let mut history = History::new(0);
+        history.push("lobby", "hi");
+
+        assert!(history.lines("lobby").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_persistent() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut history = History::persistent(4, dir.path()).await.unwrap();
+        for n in 0..10 {
+            history.push("lobby", &format!("[bob] {n}"));
+        }
+        history.push("rust", "[alice] hi");
+        history.close().await;
+
+        /* rewritten every depth lines, so the file stays short */
+        let data = std::fs::read_to_string(dir.path().join("lobby.history")).unwrap();
+        assert!(data.lines().count() <= 8);
+        assert!(data.ends_with("[bob] 9\n"));
+
+        let history = History::persistent(4, dir.path()).await.unwrap();
+        assert_eq!(
+            history.lines("lobby"),
+            vec!["[bob] 6", "[bob] 7", "[bob] 8", "[bob] 9"]
+        );
+        assert_eq!(history.lines("rust"), vec!["[alice] hi"]);
+
+        /* a shorter history on restart only keeps the newest */
+        history.close().await;
+        let history = History::persistent(2, dir.path()).await.unwrap();
+        assert_eq!(history.lines("lobby"), vec!["[bob] 8", "[bob] 9"]);
+    }
+}
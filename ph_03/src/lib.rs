@@ -0,0 +1,9 @@
+pub mod codec;
+pub mod command;
+pub mod history;
+pub mod moderation;
+pub mod queue;
+pub mod room;
+pub mod server;
+pub mod transcript;
+pub mod websocket;
@@ -1,14 +1,17 @@
 use clap::Parser;
-use futures::sink::SinkExt;
-use std::collections::HashMap;
+use ph_common::limits::{LimitArgs, Limits};
+use ph_common::tls::{Acceptor, TlsArgs};
 use std::error::Error;
-use std::sync::Arc;
-use tokio_stream::StreamExt;
-use tokio_util::codec::Framed;
-use tokio_util::codec::LinesCodec;
-use tokio::net::TcpListener;
-use tokio::net::TcpStream;
-use tokio::sync::{mpsc, Mutex};
+use std::path::PathBuf;
+use tokio::time::Duration;
+
+use ph_03::codec::{
+    InvalidUtf8, LinePolicy, LongLines, DEFAULT_MAX_LINE_LEN, DEFAULT_MAX_VIOLATIONS,
+};
+use ph_03::moderation::{FloodLimit, Moderation};
+use ph_03::queue::{SlowPolicy, DEFAULT_QUEUE_DEPTH};
+use ph_03::server::{ChatServer, DEFAULT_MAX_NAME_LEN, MIN_NAME_LEN};
+use ph_03::transcript::{Rotation, DEFAULT_KEEP, DEFAULT_MAX_BYTES};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -20,55 +23,100 @@ struct Args {
     /// IP to listen on
     #[arg(long, default_value_t = String::from("0.0.0.0"))]
     host: String,
-}
 
-/*
-#[derive(Debug)]
-enum PhMsgWrite {
-    WelcomeMsg,
-    RoomList(Vec<User>),
-    UserMsg(String),
-    UserEntered(User),
-    UserExited(User),
-}
-*/
+    /// Longest accepted username in characters, at least 16
+    #[arg(long, default_value_t = DEFAULT_MAX_NAME_LEN as u64,
+          value_parser = clap::value_parser!(u64).range(MIN_NAME_LEN as u64..))]
+    max_name_len: u64,
 
-type Tx = mpsc::UnboundedSender<String>;
-type Rx = mpsc::UnboundedReceiver<String>;
+    /// Understand slash commands like /join and /msg instead of treating them as chat
+    #[arg(long)]
+    commands: bool,
 
-#[derive(Debug)]
-struct Client {
-    rx: Rx,
-}
+    /// Lines queued for a client that is not reading before --slow-consumer applies
+    #[arg(long, default_value_t = DEFAULT_QUEUE_DEPTH,
+          value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    queue_depth: usize,
 
-impl Client {
-    async fn new(username: String, state: Arc<Mutex<PhState>>) -> Client {
-        let (tx, rx) = mpsc::unbounded_channel();
-        state.lock().await.clients.insert(username, tx);
+    /// What to do with a client whose queue is full
+    #[arg(long, value_enum, default_value_t = SlowPolicy::DropOldest)]
+    slow_consumer: SlowPolicy,
 
-        Client { rx }
-    }
-}
+    /// Chat lines per room replayed to users joining it, 0 to disable
+    #[arg(long, default_value_t = 0)]
+    history: usize,
 
-#[derive(Debug)]
-struct PhState {
-    clients: HashMap<String, Tx>,
-}
+    /// Directory to keep the history in across restarts
+    #[arg(long)]
+    history_dir: Option<PathBuf>,
+
+    /// Longest accepted line in bytes
+    #[arg(long, default_value_t = DEFAULT_MAX_LINE_LEN)]
+    max_line_len: usize,
+
+    /// What to do with lines longer than --max-line-len
+    #[arg(long, value_enum, default_value_t = LongLines::Truncate)]
+    long_lines: LongLines,
+
+    /// What to do with lines that are not valid UTF-8
+    #[arg(long, value_enum, default_value_t = InvalidUtf8::Lossy)]
+    invalid_utf8: InvalidUtf8,
+
+    /// Disconnect clients after this many over-long or invalid lines, 0 never
+    #[arg(long, default_value_t = DEFAULT_MAX_VIOLATIONS)]
+    max_violations: u32,
+
+    /// Words starred out of chat, comma separated, any case
+    #[arg(long, value_delimiter = ',')]
+    banned_words: Vec<String>,
+
+    /// Users allowed to /kick and /mute others, comma separated, needs --commands.
+    /// Their names are reserved, an admin joins with `<name> <admin token>`
+    #[arg(long, value_delimiter = ',', requires = "admin_token")]
+    admins: Vec<String>,
+
+    /// Token admins give after their name when joining
+    #[arg(long)]
+    admin_token: Option<String>,
 
-impl PhState {
-    fn new() -> PhState {
-        PhState {
-            clients: HashMap::new(),
-        }
-    }
-
-    async fn broadcast(&mut self, sender: &str, msg: &str) {
-        for client in self.clients.iter_mut() {
-            if *client.0 != sender {
-                let _ = client.1.send(msg.into());
-            }
-        }
-    }
+    /// Mute users sending more than this many lines within --flood-window
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    flood_lines: Option<usize>,
+
+    /// Seconds over which --flood-lines are counted
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    flood_window: u64,
+
+    /// Seconds a flooding user stays muted, at most a day
+    #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u64).range(..=86400))]
+    flood_mute: u64,
+
+    /// Log every join, leave and message to --transcript-path as JSON Lines
+    #[arg(long)]
+    transcript: bool,
+
+    /// File the transcript is written to, rotated ones get .1, .2, ... appended
+    #[arg(long, default_value = "transcript.jsonl")]
+    transcript_path: PathBuf,
+
+    /// Size in bytes at which the transcript file is rotated
+    #[arg(long, default_value_t = DEFAULT_MAX_BYTES,
+          value_parser = clap::value_parser!(u64).range(1..))]
+    transcript_max_bytes: u64,
+
+    /// Rotated transcript files kept, older ones are deleted
+    #[arg(long, default_value_t = DEFAULT_KEEP)]
+    transcript_keep: usize,
+
+    /// Also accept WebSocket clients on this port, one text frame per line
+    #[arg(long)]
+    ws_port: Option<u16>,
+
+    #[command(flatten)]
+    tls: TlsArgs,
+
+    #[command(flatten)]
+    limits: LimitArgs,
 }
 
 #[tokio::main]
@@ -78,98 +126,39 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let hostname = format!("{}:{}", args.host, args.port);
     println!("Will start listening on {hostname}");
 
-    let listener = TcpListener::bind(hostname).await?;
-    let state = Arc::new(Mutex::new(PhState::new()));
-
-    loop {
-        let (stream, _addr) = listener.accept().await?;
-        let state = state.clone();
-
-        tokio::spawn(async move {
-            if let Err(e) = handle_client(stream, state).await {
-                println!("Error occurred: {e:?}");
-            }
-        });
-    }
-}
-
-async fn handle_client(
-    stream: TcpStream,
-    state: Arc<Mutex<PhState>>,
-) -> Result<(), Box<dyn Error>> {
-    println!("New connection: {}", stream.peer_addr().unwrap());
-
-    let mut codec = Framed::new(stream, LinesCodec::new_with_max_length(2000));
-
-    codec.send("Welcome! What is your name?").await.unwrap();
-
-    let username = match codec.next().await {
-        Some(Ok(name)) => name,
-        _ => {
-            println!("Failed to read username");
-            return Ok(());
-        }
-    };
-
-    if username.is_empty() {
-        println!("Username not set, abort");
-        return Ok(());
-    }
-    if !username.chars().all(char::is_alphanumeric) {
-        println!("Username contains non-alphanumeric characters, abort");
-        return Ok(());
-    }
-
-    {
-        let state = state.lock().await;
-        let usernames = state
-            .clients
-            .keys()
-            .map(|s| &**s)
-            .collect::<Vec<_>>()
-            .join(", ");
-        let msg = "* The room contains: ".to_owned() + &usernames;
-        codec.send(&msg).await?;
-    }
-
-    let mut client = Client::new(username.clone(), state.clone()).await;
-
-    {
-        let mut state = state.lock().await;
-        let msg = format!("* {username} has entered the room");
-        state.broadcast(&username, &msg).await;
-    }
-
-    loop {
-        tokio::select! {
-             Some(msg) = client.rx.recv() => {
-                 println!("Sending: {msg}");
-                 codec.send(&msg).await?;
-             },
-             result = codec.next() => match result {
-                 Some(Ok(msg)) => {
-                     println!("Received: {msg}");
-                     let mut state = state.lock().await;
-
-                     let msg = format!("[{username}] {msg}");
-                     state.broadcast(&username, &msg).await;
-                 },
-                 Some(Err(e)) => {
-                     println!("Error: {e:?}");
-                 }
-                 None => break,
-             },
-        }
-    }
-
-    {
-        println!("Closing connection");
-        let mut state = state.lock().await;
-        state.clients.remove(&username);
-
-        let msg = format!("* {username} has left the room");
-        state.broadcast(&username, &msg).await;
-    }
+    let server = ChatServer::new()
+        .with_max_name_len(args.max_name_len as usize)
+        .with_commands(args.commands)
+        .with_queue_depth(args.queue_depth)
+        .with_slow_policy(args.slow_consumer)
+        .with_history(args.history)
+        .with_history_dir(args.history_dir)
+        .with_transcript(args.transcript.then_some(args.transcript_path))
+        .with_transcript_rotation(Rotation {
+            max_bytes: args.transcript_max_bytes,
+            keep: args.transcript_keep,
+        })
+        .with_websocket(args.ws_port.map(|port| format!("{}:{port}", args.host)))
+        .with_line_policy(LinePolicy {
+            max_len: args.max_line_len,
+            long_lines: args.long_lines,
+            invalid_utf8: args.invalid_utf8,
+            max_violations: args.max_violations,
+        })
+        .with_moderation(
+            Moderation::new()
+                .with_banned_words(&args.banned_words)
+                .with_admins(args.admins)
+                .with_admin_token(args.admin_token)
+                .with_flood_limit(args.flood_lines.map(|messages| FloodLimit {
+                    messages,
+                    window: Duration::from_secs(args.flood_window),
+                    mute: Duration::from_secs(args.flood_mute),
+                })),
+        )
+        .with_tls(Acceptor::from_args(&args.tls)?)
+        .with_limits(Limits::new(args.limits));
+    server.run(hostname).await?;
 
     Ok(())
 }
@@ -0,0 +1,268 @@
+use std::collections::{HashSet, VecDeque};
+use subtle::ConstantTimeEq;
+use tokio::time::{Duration, Instant};
+
+/* how long /mute silences someone unless told otherwise */
+pub const DEFAULT_MUTE: Duration = Duration::from_secs(300);
+/* the longest anyone can be muted for, by /mute or for flooding */
+pub const MAX_MUTE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/* more than `messages` lines within `window` mutes the sender for `mute` */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FloodLimit {
+    pub messages: usize,
+    pub window: Duration,
+    pub mute: Duration,
+}
+
+/*
+ * Server side moderation settings. Admin names are reserved: joining under
+ * one takes the admin token after the name, and nobody can /nick to one.
+ * Without a token nobody gets to be an admin.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Moderation {
+    /* lowercase, matched against whole words */
+    banned_words: HashSet<String>,
+    admins: HashSet<String>,
+    admin_token: Option<String>,
+    flood: Option<FloodLimit>,
+}
+
+impl Moderation {
+    pub fn new() -> Moderation {
+        Moderation::default()
+    }
+
+    pub fn with_banned_words<I, S>(mut self, words: I) -> Moderation
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.banned_words = words
+            .into_iter()
+            .map(|word| word.as_ref().to_lowercase())
+            .collect();
+        self
+    }
+
+    pub fn with_admins<I, S>(mut self, admins: I) -> Moderation
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.admins = admins.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_admin_token(mut self, token: Option<String>) -> Moderation {
+        self.admin_token = token;
+        self
+    }
+
+    pub fn with_flood_limit(mut self, flood: Option<FloodLimit>) -> Moderation {
+        self.flood = flood;
+        self
+    }
+
+    pub fn is_admin(&self, name: &str) -> bool {
+        self.admins.contains(name)
+    }
+
+    /* why a client joining as name, with the token it gave if any, is refused */
+    pub fn check_join(&self, name: &str, token: Option<&str>) -> Result<(), String> {
+        match token {
+            None if self.is_admin(name) => Err(format!("Name {name} is reserved")),
+            None => Ok(()),
+            Some(token) if self.is_admin(name) && self.token_matches(token) => Ok(()),
+            Some(_) => Err("Invalid admin token".to_string()),
+        }
+    }
+
+    /* constant time, so timing the answers does not give the token away */
+    fn token_matches(&self, token: &str) -> bool {
+        match &self.admin_token {
+            Some(expected) => token.as_bytes().ct_eq(expected.as_bytes()).into(),
+            None => false,
+        }
+    }
+
+    pub fn flood_limit(&self) -> Option<&FloodLimit> {
+        self.flood.as_ref()
+    }
+
+    /* banned words, in any case, are starred out letter for letter */
+    pub fn filter(&self, text: &str) -> String {
+        if self.banned_words.is_empty() {
+            return text.to_string();
+        }
+
+        let mut filtered = String::with_capacity(text.len());
+        let mut word = String::new();
+        for c in text.chars().chain(std::iter::once(' ')) {
+            if c.is_alphanumeric() {
+                word.push(c);
+                continue;
+            }
+            if self.banned_words.contains(&word.to_lowercase()) {
+                filtered.extend(word.chars().map(|_| '*'));
+            } else {
+                filtered.push_str(&word);
+            }
+            word.clear();
+            filtered.push(c);
+        }
+        filtered.pop();
+
+        filtered
+    }
+}
+
+/* what the moderation keeps track of for each member */
+#[derive(Debug, Default)]
+pub struct Conduct {
+    recent: VecDeque<Instant>,
+    muted_until: Option<Instant>,
+}
+
+impl Conduct {
+    /* what is left of a mute, if any */
+    pub fn muted_for(&self, now: Instant) -> Option<Duration> {
+        self.muted_until
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    /* never longer than MAX_MUTE */
+    pub fn mute(&mut self, now: Instant, duration: Duration) {
+        let duration = duration.min(MAX_MUTE);
+        self.muted_until = Some(now.checked_add(duration).unwrap_or(now + MAX_MUTE));
+    }
+
+    pub fn unmute(&mut self) {
+        self.muted_until = None;
+    }
+
+    /* counts a line, true if that just got the member muted for flooding */
+    pub fn record(&mut self, now: Instant, limit: &FloodLimit) -> bool {
+        while self
+            .recent
+            .front()
+            .is_some_and(|sent| now - *sent >= limit.window)
+        {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(now);
+
+        if self.recent.len() > limit.messages && self.muted_for(now).is_none() {
+            self.recent.clear();
+            self.mute(now, limit.mute);
+            return true;
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let moderation = Moderation::new().with_banned_words(["darn", "Heck"]);
+
+        assert_eq!(moderation.filter("well darn it"), "well **** it");
+        assert_eq!(moderation.filter("HECK, DaRn!"), "****, ****!");
+        assert_eq!(moderation.filter("darnit heckler"), "darnit heckler");
+        assert_eq!(moderation.filter(""), "");
+        assert_eq!(Moderation::new().filter("darn"), "darn");
+    }
+
+    #[test]
+    fn test_admins() {
+        let moderation = Moderation::new().with_admins(["alice"]);
+
+        assert!(moderation.is_admin("alice"));
+        assert!(!moderation.is_admin("Alice"));
+        assert!(!moderation.is_admin("bob"));
+    }
+
+    #[test]
+    fn test_check_join() {
+        let moderation = Moderation::new()
+            .with_admins(["alice"])
+            .with_admin_token(Some("s3cret".to_string()));
+
+        assert_eq!(moderation.check_join("bob", None), Ok(()));
+        assert_eq!(moderation.check_join("alice", Some("s3cret")), Ok(()));
+        assert_eq!(
+            moderation.check_join("alice", None),
+            Err("Name alice is reserved".to_string())
+        );
+        assert_eq!(
+            moderation.check_join("alice", Some("guess")),
+            Err("Invalid admin token".to_string())
+        );
+        assert_eq!(
+            moderation.check_join("bob", Some("s3cret")),
+            Err("Invalid admin token".to_string())
+        );
+
+        /* without a token the admin names are simply unusable */
+        let moderation = Moderation::new().with_admins(["alice"]);
+        assert!(moderation.check_join("alice", None).is_err());
+        assert!(moderation.check_join("alice", Some("")).is_err());
+    }
+
+    #[test]
+    fn test_flood() {
+        let limit = FloodLimit {
+            messages: 3,
+            window: Duration::from_secs(10),
+            mute: Duration::from_secs(60),
+        };
+        let start = Instant::now();
+        let mut conduct = Conduct::default();
+
+        /* spread out is fine */
+        for n in 0..10 {
+            assert!(!conduct.record(start + Duration::from_secs(4 * n), &limit));
+        }
+
+        let now = start + Duration::from_secs(100);
+        for _ in 0..3 {
+            assert!(!conduct.record(now, &limit));
+        }
+        assert!(conduct.record(now, &limit));
+        assert_eq!(conduct.muted_for(now), Some(Duration::from_secs(60)));
+
+        /* already muted, no second notice */
+        assert!(!conduct.record(now, &limit));
+
+        let later = now + Duration::from_secs(60);
+        assert_eq!(conduct.muted_for(later), None);
+    }
+
+    #[test]
+    fn test_mute() {
+        let now = Instant::now();
+        let mut conduct = Conduct::default();
+        assert_eq!(conduct.muted_for(now), None);
+
+        conduct.mute(now, Duration::from_secs(5));
+        assert_eq!(
+            conduct.muted_for(now + Duration::from_secs(2)),
+            Some(Duration::from_secs(3))
+        );
+
+        conduct.unmute();
+        assert_eq!(conduct.muted_for(now), None);
+
+        /* an absurd duration is capped rather than overflowing the clock */
+        conduct.mute(now, Duration::from_secs(u64::MAX));
+        assert_eq!(conduct.muted_for(now), Some(MAX_MUTE));
+        conduct.mute(now, Duration::MAX);
+        assert_eq!(conduct.muted_for(now), Some(MAX_MUTE));
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+pub const DEFAULT_QUEUE_DEPTH: usize = 256;
+
+/* what happens to a client whose queue is full when another line arrives */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SlowPolicy {
+    /// Forget the oldest queued line to make room, the client misses some chat
+    #[default]
+    DropOldest,
+    /// Close the queue, the client is told and disconnected
+    Disconnect,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    lines: VecDeque<String>,
+    /* no more lines, either the sender is gone or the client fell behind */
+    closed: bool,
+    overflowed: bool,
+    dropped: u64,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    inner: Mutex<Inner>,
+    notify: Notify,
+}
+
+/*
+ * A bounded queue of lines for one client. Unlike a bounded mpsc channel,
+ * sending never waits, a full queue is resolved by the policy instead so one
+ * stalled reader cannot hold up everybody else.
+ */
+pub fn channel(depth: usize, policy: SlowPolicy) -> (Sender, Receiver) {
+    let shared = Arc::new(Shared::default());
+    let sender = Sender {
+        shared: shared.clone(),
+        depth: depth.max(1),
+        policy,
+    };
+
+    (sender, Receiver { shared })
+}
+
+/* the line was not queued as the receiver fell behind and was cut off */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overflow;
+
+#[derive(Debug)]
+pub struct Sender {
+    shared: Arc<Shared>,
+    depth: usize,
+    policy: SlowPolicy,
+}
+
+impl Sender {
+    pub fn send(&self, line: String) -> Result<(), Overflow> {
+        let mut inner = self.shared.inner.lock().unwrap();
+        if inner.closed {
+            return Err(Overflow);
+        }
+
+        if inner.lines.len() >= self.depth {
+            match self.policy {
+                SlowPolicy::DropOldest => {
+                    inner.lines.pop_front();
+                    inner.dropped += 1;
+                }
+                SlowPolicy::Disconnect => {
+                    inner.lines.clear();
+                    inner.closed = true;
+                    inner.overflowed = true;
+                    drop(inner);
+                    self.shared.notify.notify_one();
+                    return Err(Overflow);
+                }
+            }
+        }
+
+        inner.lines.push_back(line);
+        drop(inner);
+        self.shared.notify.notify_one();
+
+        Ok(())
+    }
+
+    /*
+     * Queues regardless of the depth, for the replies to a client's own
+     * commands. A client whose writes stall stops being read as well, so it
+     * cannot pile those up.
+     */
+    pub fn reply(&self, line: String) {
+        let mut inner = self.shared.inner.lock().unwrap();
+        if inner.closed {
+            return;
+        }
+
+        inner.lines.push_back(line);
+        drop(inner);
+        self.shared.notify.notify_one();
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.shared.inner.lock().unwrap().closed = true;
+        self.shared.notify.notify_one();
+    }
+}
+
+#[derive(Debug)]
+pub struct Receiver {
+    shared: Arc<Shared>,
+}
+
+impl Receiver {
+    /* None once closed, cancel safe so it can be used in select! */
+    pub async fn recv(&mut self) -> Option<String> {
+        loop {
+            {
+                let mut inner = self.shared.inner.lock().unwrap();
+                if let Some(line) = inner.lines.pop_front() {
+                    return Some(line);
+                }
+                if inner.closed {
+                    return None;
+                }
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.inner.lock().unwrap().lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /* closed by the Disconnect policy rather than by the sender going away */
+    pub fn overflowed(&self) -> bool {
+        self.shared.inner.lock().unwrap().overflowed
+    }
+
+    /* lines lost to the DropOldest policy */
+    pub fn dropped(&self) -> u64 {
+        self.shared.inner.lock().unwrap().dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_in_order() {
+        let (tx, mut rx) = channel(4, SlowPolicy::DropOldest);
+        tx.send("a".to_string()).unwrap();
+        tx.send("b".to_string()).unwrap();
+
+        assert_eq!(rx.recv().await.unwrap(), "a");
+        assert_eq!(rx.recv().await.unwrap(), "b");
+
+        drop(tx);
+        assert_eq!(rx.recv().await, None);
+        assert!(!rx.overflowed());
+    }
+
+    #[tokio::test]
+    async fn test_wakes_receiver() {
+        let (tx, mut rx) = channel(4, SlowPolicy::DropOldest);
+        let receiver = tokio::spawn(async move { rx.recv().await });
+
+        tokio::task::yield_now().await;
+        tx.send("late".to_string()).unwrap();
+        assert_eq!(receiver.await.unwrap().unwrap(), "late");
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest() {
+        let (tx, mut rx) = channel(16, SlowPolicy::DropOldest);
+
+        /* nobody reading, the queue stays at its depth */
+        for n in 0..100_000 {
+            tx.send(n.to_string()).unwrap();
+            assert!(rx.len() <= 16);
+        }
+        assert_eq!(rx.len(), 16);
+        assert_eq!(rx.dropped(), 100_000 - 16);
+
+        assert_eq!(rx.recv().await.unwrap(), (100_000 - 16).to_string());
+    }
+
+    #[tokio::test]
+    async fn test_disconnect() {
+        let (tx, mut rx) = channel(2, SlowPolicy::Disconnect);
+
+        tx.send("a".to_string()).unwrap();
+        tx.send("b".to_string()).unwrap();
+        assert_eq!(tx.send("c".to_string()), Err(Overflow));
+        assert_eq!(tx.send("d".to_string()), Err(Overflow));
+
+        assert!(rx.is_empty());
+        assert_eq!(rx.recv().await, None);
+        assert!(rx.overflowed());
+    }
+
+    #[tokio::test]
+    async fn test_reply() {
+        let (tx, mut rx) = channel(1, SlowPolicy::Disconnect);
+
+        tx.send("a".to_string()).unwrap();
+        tx.reply("b".to_string());
+        tx.reply("c".to_string());
+        assert_eq!(rx.len(), 3);
+
+        assert_eq!(rx.recv().await.unwrap(), "a");
+        assert_eq!(rx.recv().await.unwrap(), "b");
+        assert_eq!(rx.recv().await.unwrap(), "c");
+    }
+}
@@ -0,0 +1,635 @@
+use std::collections::{BTreeMap, HashMap};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{Duration, Instant};
+
+use crate::command::{Command, MUTE_USAGE};
+use crate::history::History;
+use crate::moderation::{Conduct, DEFAULT_MUTE};
+use crate::queue::{self, Receiver, Sender};
+use crate::server::{validate_name, validate_room, ChatConfig, DEFAULT_ROOM};
+use crate::transcript::{Event, Transcript};
+
+/* requests waiting for the room task before clients have to wait too */
+const REQUEST_QUEUE_LEN: usize = 1024;
+
+pub type ClientId = u64;
+
+#[derive(Debug)]
+enum Request {
+    Join {
+        name: String,
+        token: Option<String>,
+        reply: oneshot::Sender<Result<(ClientId, Receiver), String>>,
+    },
+    Line {
+        id: ClientId,
+        line: String,
+    },
+    Leave {
+        id: ClientId,
+    },
+}
+
+/*
+ * Handle to the task owning every room and member. Joins, lines and leaves
+ * are requests handled strictly one after another by that task, so all chat
+ * has a single order which everybody sees the same. Fan-out only ever pushes
+ * to the members' queues, so nothing in there waits on a client.
+ */
+#[derive(Debug, Clone)]
+pub struct RoomHandle {
+    tx: mpsc::Sender<Request>,
+}
+
+impl RoomHandle {
+    /* the task ends once the last handle is dropped */
+    pub fn spawn(config: ChatConfig, history: History, transcript: Transcript) -> RoomHandle {
+        let (tx, mut rx) = mpsc::channel(REQUEST_QUEUE_LEN);
+        let mut rooms = Rooms::new(config, history).with_transcript(transcript);
+
+        tokio::spawn(async move {
+            while let Some(request) = rx.recv().await {
+                rooms.handle(request);
+            }
+        });
+
+        RoomHandle { tx }
+    }
+
+    /*
+     * Err is the reason the name was refused, admin names need the admin
+     * token. The receiver already holds the room listing and history,
+     * everything after comes in room order.
+     */
+    pub async fn join(
+        &self,
+        name: String,
+        token: Option<String>,
+    ) -> Result<(ClientId, Receiver), String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(Request::Join { name, token, reply })
+            .await
+            .map_err(|_| "Server is shutting down".to_string())?;
+
+        rx.await
+            .unwrap_or_else(|_| Err("Server is shutting down".to_string()))
+    }
+
+    /* chat or, with commands enabled, a command whose replies go to the queue */
+    pub async fn line(&self, id: ClientId, line: String) {
+        let _ = self.tx.send(Request::Line { id, line }).await;
+    }
+
+    pub async fn leave(&self, id: ClientId) {
+        let _ = self.tx.send(Request::Leave { id }).await;
+    }
+}
+
+#[derive(Debug)]
+struct Member {
+    name: String,
+    room: String,
+    tx: Sender,
+    conduct: Conduct,
+}
+
+/* only ever touched by the room task */
+#[derive(Debug)]
+struct Rooms {
+    config: ChatConfig,
+    members: HashMap<ClientId, Member>,
+    history: History,
+    transcript: Transcript,
+    next_id: ClientId,
+}
+
+impl Rooms {
+    fn new(config: ChatConfig, history: History) -> Rooms {
+        Rooms {
+            config,
+            members: HashMap::new(),
+            history,
+            transcript: Transcript::default(),
+            next_id: 0,
+        }
+    }
+
+    fn with_transcript(mut self, transcript: Transcript) -> Rooms {
+        self.transcript = transcript;
+        self
+    }
+
+    fn handle(&mut self, request: Request) {
+        match request {
+            Request::Join { name, token, reply } => {
+                let _ = reply.send(self.join(name, token.as_deref()));
+            }
+            Request::Line { id, line } => self.line(id, line),
+            Request::Leave { id } => self.leave(id),
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<ClientId> {
+        self.members
+            .iter()
+            .find(|(_, member)| member.name == name)
+            .map(|(id, _)| *id)
+    }
+
+    fn join(&mut self, name: String, token: Option<&str>) -> Result<(ClientId, Receiver), String> {
+        validate_name(&name, self.config.max_name_len)?;
+        self.config.moderation.check_join(&name, token)?;
+        if self.find(&name).is_some() {
+            return Err(format!("Name {name} is already taken"));
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let (tx, rx) = queue::channel(self.config.queue_depth, self.config.slow_policy);
+        for line in self.welcome(DEFAULT_ROOM, id) {
+            tx.reply(line);
+        }
+
+        let msg = format!("* {name} has entered the room");
+        self.broadcast(DEFAULT_ROOM, id, &msg);
+        self.transcript.record(Event::Join {
+            user: &name,
+            room: DEFAULT_ROOM,
+        });
+        let member = Member {
+            name,
+            room: DEFAULT_ROOM.to_string(),
+            tx,
+            conduct: Conduct::default(),
+        };
+        self.members.insert(id, member);
+
+        Ok((id, rx))
+    }
+
+    fn line(&mut self, id: ClientId, line: String) {
+        let Some(member) = self.members.get_mut(&id) else {
+            return;
+        };
+
+        /* commands count too, or they would be a way around it */
+        if let Some(limit) = self.config.moderation.flood_limit() {
+            if member.conduct.record(Instant::now(), limit) {
+                let secs = limit.mute.as_secs();
+                member
+                    .tx
+                    .reply(format!("* You are muted for {secs} seconds for flooding"));
+                return;
+            }
+        }
+
+        if let Some(command) = Command::parse(&line).filter(|_| self.config.commands) {
+            for reply in self.command(id, command) {
+                if let Some(member) = self.members.get(&id) {
+                    member.tx.reply(reply);
+                }
+            }
+            return;
+        }
+        let notice = self.muted(id);
+        let Some(member) = self.members.get(&id) else {
+            return;
+        };
+        if let Some(notice) = notice {
+            member.tx.reply(notice);
+            return;
+        }
+
+        let room = member.room.clone();
+        let text = self.config.moderation.filter(&line);
+        self.transcript.record(Event::Message {
+            user: &member.name,
+            room: &room,
+            text: &text,
+        });
+        let msg = format!("[{}] {text}", member.name);
+        self.say(&room, id, &msg);
+    }
+
+    fn leave(&mut self, id: ClientId) {
+        let Some(member) = self.members.remove(&id) else {
+            return;
+        };
+
+        let msg = format!("* {} has left the room", member.name);
+        self.broadcast(&member.room, id, &msg);
+        self.transcript.record(Event::Leave {
+            user: &member.name,
+            room: &member.room,
+        });
+    }
+
+    /* what a muted member is told instead of their line going out */
+    fn muted(&self, id: ClientId) -> Option<String> {
+        let left = self.members.get(&id)?.conduct.muted_for(Instant::now())?;
+        Some(format!(
+            "* You are muted for another {} seconds",
+            seconds(left)
+        ))
+    }
+
+    /* everybody else in the room, as listed when joining it */
+    fn members(&self, room: &str, id: ClientId) -> String {
+        let mut names = self
+            .members
+            .iter()
+            .filter(|(other, member)| member.room == room && **other != id)
+            .map(|(_, member)| &*member.name)
+            .collect::<Vec<_>>();
+        names.sort();
+        names.join(", ")
+    }
+
+    /* the default room is always there, others only while occupied */
+    fn rooms(&self) -> Vec<(&str, usize)> {
+        let mut rooms = BTreeMap::from([(DEFAULT_ROOM, 0)]);
+        for member in self.members.values() {
+            *rooms.entry(&*member.room).or_default() += 1;
+        }
+        rooms.into_iter().collect()
+    }
+
+    /* the room listing, followed by the room's recent chat */
+    fn welcome(&self, room: &str, id: ClientId) -> Vec<String> {
+        let mut lines = vec![format!("* The room contains: {}", self.members(room, id))];
+        lines.extend(self.history.lines(room));
+        lines
+    }
+
+    fn broadcast(&self, room: &str, sender: ClientId, msg: &str) {
+        for (id, member) in self.members.iter() {
+            if *id != sender && member.room == room {
+                let _ = member.tx.send(msg.into());
+            }
+        }
+    }
+
+    /* chat that ends up in the room's history, unlike presence notices */
+    fn say(&mut self, room: &str, sender: ClientId, msg: &str) {
+        self.broadcast(room, sender, msg);
+        self.history.push(room, msg);
+    }
+
+    /* announces the move in both rooms */
+    fn move_to(&mut self, id: ClientId, room: &str) {
+        let Some(member) = self.members.get_mut(&id) else {
+            return;
+        };
+        let name = member.name.clone();
+        let old = std::mem::replace(&mut member.room, room.to_string());
+
+        self.broadcast(&old, id, &format!("* {name} has left the room"));
+        self.broadcast(room, id, &format!("* {name} has entered the room"));
+        self.transcript.record(Event::Leave {
+            user: &name,
+            room: &old,
+        });
+        self.transcript.record(Event::Join { user: &name, room });
+    }
+
+    /*
+     * The lines sent back to the client issuing the command. Quit never gets
+     * here, the client simply disconnects.
+     */
+    fn command(&mut self, id: ClientId, command: Command) -> Vec<String> {
+        let Some(member) = self.members.get(&id) else {
+            return Vec::new();
+        };
+        let name = member.name.clone();
+        let room = member.room.clone();
+        let max_len = self.config.max_name_len;
+
+        let reply = match command {
+            Command::Join(target) => {
+                if let Err(reason) = validate_room(&target, max_len) {
+                    return vec![format!("* {reason}")];
+                }
+                if target == room {
+                    return vec![format!("* You are already in {room}")];
+                }
+                self.move_to(id, &target);
+                return self.welcome(&target, id);
+            }
+            Command::Leave => {
+                if room == DEFAULT_ROOM {
+                    return vec![format!("* You are already in {DEFAULT_ROOM}")];
+                }
+                self.move_to(id, DEFAULT_ROOM);
+                return self.welcome(DEFAULT_ROOM, id);
+            }
+            Command::Rooms => {
+                let rooms = self
+                    .rooms()
+                    .into_iter()
+                    .map(|(name, count)| format!("{name} ({count})"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("* Rooms: {rooms}")
+            }
+            Command::Who => format!("* The room contains: {}", self.members(&room, id)),
+            Command::Msg { to, text } => {
+                if text.is_empty() {
+                    return vec!["* Usage: /msg <user> <text>".to_string()];
+                }
+                if let Some(notice) = self.muted(id) {
+                    return vec![notice];
+                }
+                let text = self.config.moderation.filter(&text);
+                let Some(other) = self.find(&to).and_then(|other| self.members.get(&other)) else {
+                    return vec![format!("* No user named {to}")];
+                };
+                self.transcript.record(Event::Private {
+                    user: &name,
+                    to: &to,
+                    text: &text,
+                });
+                let msg = format!("[{name} -> {to}] {text}");
+                let _ = other.tx.send(msg.clone());
+                msg
+            }
+            Command::Me(action) => {
+                if action.is_empty() {
+                    return vec!["* Usage: /me <action>".to_string()];
+                }
+                if let Some(notice) = self.muted(id) {
+                    return vec![notice];
+                }
+                let action = self.config.moderation.filter(&action);
+                self.transcript.record(Event::Action {
+                    user: &name,
+                    room: &room,
+                    text: &action,
+                });
+                self.say(&room, id, &format!("* {name} {action}"));
+                return Vec::new();
+            }
+            Command::Nick(nick) => {
+                if let Err(reason) = validate_name(&nick, max_len) {
+                    return vec![format!("* {reason}")];
+                }
+                if self.find(&nick).is_some() {
+                    return vec![format!("* Name {nick} is already taken")];
+                }
+                if self.config.moderation.is_admin(&nick) {
+                    return vec![format!("* Name {nick} is reserved")];
+                }
+                if let Some(member) = self.members.get_mut(&id) {
+                    member.name = nick.clone();
+                }
+                self.broadcast(&room, id, &format!("* {name} is now known as {nick}"));
+                self.transcript.record(Event::Nick {
+                    user: &name,
+                    nick: &nick,
+                });
+                format!("* You are now known as {nick}")
+            }
+            Command::Quit => return Vec::new(),
+            Command::Usage(usage) => format!("* Usage: {usage}"),
+            Command::Kick(user) => {
+                let other = match self.moderate(&name, &user, "/kick <user>") {
+                    Ok(other) => other,
+                    Err(reason) => return vec![reason],
+                };
+                /* dropping the queue disconnects them once this is sent */
+                if let Some(kicked) = self.members.remove(&other) {
+                    kicked.tx.reply(format!("* You were kicked by {name}"));
+                    self.broadcast(&kicked.room, id, &format!("* {user} was kicked"));
+                    self.transcript.record(Event::Leave {
+                        user: &user,
+                        room: &kicked.room,
+                    });
+                }
+                format!("* Kicked {user}")
+            }
+            Command::Mute { user, seconds } => {
+                let other = match self.moderate(&name, &user, MUTE_USAGE) {
+                    Ok(other) => other,
+                    Err(reason) => return vec![reason],
+                };
+                let duration = seconds.map_or(DEFAULT_MUTE, Duration::from_secs);
+                let secs = duration.as_secs();
+                if let Some(muted) = self.members.get_mut(&other) {
+                    muted.conduct.mute(Instant::now(), duration);
+                    muted
+                        .tx
+                        .reply(format!("* You were muted by {name} for {secs} seconds"));
+                }
+                format!("* Muted {user} for {secs} seconds")
+            }
+            Command::Unmute(user) => {
+                let other = match self.moderate(&name, &user, "/unmute <user>") {
+                    Ok(other) => other,
+                    Err(reason) => return vec![reason],
+                };
+                if let Some(muted) = self.members.get_mut(&other) {
+                    muted.conduct.unmute();
+                    muted.tx.reply(format!("* You were unmuted by {name}"));
+                }
+                format!("* Unmuted {user}")
+            }
+        };
+
+        vec![reply]
+    }
+
+    /* the target of a moderation command, or why there is none */
+    fn moderate(&self, admin: &str, user: &str, usage: &str) -> Result<ClientId, String> {
+        if !self.config.moderation.is_admin(admin) {
+            return Err("* Only admins may do that".to_string());
+        }
+        if user.is_empty() {
+            return Err(format!("* Usage: {usage}"));
+        }
+        if user == admin {
+            return Err("* You cannot do that to yourself".to_string());
+        }
+
+        self.find(user)
+            .ok_or_else(|| format!("* No user named {user}"))
+    }
+}
+
+/* whole seconds, rounded up so a mute never reads as 0 seconds left */
+fn seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moderation::Moderation;
+
+    async fn recv_all(rx: &mut Receiver) -> Vec<String> {
+        let mut lines = Vec::new();
+        while !rx.is_empty() {
+            lines.push(rx.recv().await.unwrap());
+        }
+        lines
+    }
+
+    #[test]
+    fn test_rooms() {
+        let mut rooms = Rooms::new(ChatConfig::default(), History::default());
+        let (alice, _alice_rx) = rooms.join("alice".to_string(), None).unwrap();
+        let (bob, _bob_rx) = rooms.join("bob".to_string(), None).unwrap();
+        assert_eq!(rooms.rooms(), vec![(DEFAULT_ROOM, 2)]);
+
+        rooms.members.get_mut(&bob).unwrap().room = "rust".to_string();
+        assert_eq!(rooms.rooms(), vec![(DEFAULT_ROOM, 1), ("rust", 1)]);
+        assert_eq!(rooms.members(DEFAULT_ROOM, bob), "alice");
+        assert_eq!(rooms.members("rust", bob), "");
+
+        rooms.leave(alice);
+        rooms.leave(bob);
+        assert_eq!(rooms.rooms(), vec![(DEFAULT_ROOM, 0)]);
+    }
+
+    #[test]
+    fn test_join_refused() {
+        let mut rooms = Rooms::new(ChatConfig::default(), History::default());
+        let _alice = rooms.join("alice".to_string(), None).unwrap();
+
+        assert_eq!(
+            rooms.join("alice".to_string(), None).unwrap_err(),
+            "Name alice is already taken"
+        );
+        assert_eq!(
+            rooms.join("al ice".to_string(), None).unwrap_err(),
+            "Name may only contain letters and digits"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_moderation() {
+        let mut config = ChatConfig {
+            commands: true,
+            ..Default::default()
+        };
+        config.moderation = Moderation::new()
+            .with_banned_words(["heck"])
+            .with_admins(["alice", "root"])
+            .with_admin_token(Some("s3cret".to_string()));
+        let mut rooms = Rooms::new(config, History::default());
+        assert_eq!(
+            rooms.join("alice".to_string(), None).unwrap_err(),
+            "Name alice is reserved"
+        );
+        let (alice, mut alice_rx) = rooms.join("alice".to_string(), Some("s3cret")).unwrap();
+        let (bob, mut bob_rx) = rooms.join("bob".to_string(), None).unwrap();
+        recv_all(&mut alice_rx).await;
+        recv_all(&mut bob_rx).await;
+
+        /* nor can anybody become an admin by renaming */
+        rooms.line(bob, "/nick root".to_string());
+        assert_eq!(recv_all(&mut bob_rx).await, vec!["* Name root is reserved"]);
+        rooms.line(bob, "/kick alice".to_string());
+        assert_eq!(
+            recv_all(&mut bob_rx).await,
+            vec!["* Only admins may do that"]
+        );
+
+        rooms.line(bob, "/me says heck".to_string());
+        assert_eq!(recv_all(&mut alice_rx).await, vec!["* bob says ****"]);
+
+        rooms.line(alice, "/kick alice".to_string());
+        rooms.line(alice, "/mute".to_string());
+        rooms.line(alice, "/unmute nobody".to_string());
+        assert_eq!(
+            recv_all(&mut alice_rx).await,
+            vec![
+                "* You cannot do that to yourself",
+                "* Usage: /mute <user> [seconds]",
+                "* No user named nobody"
+            ]
+        );
+
+        /* muted members still get to use the other commands */
+        rooms.line(alice, "/mute bob".to_string());
+        rooms.line(bob, "/msg alice psst".to_string());
+        rooms.line(bob, "/who".to_string());
+        assert_eq!(
+            recv_all(&mut bob_rx).await,
+            vec![
+                "* You were muted by alice for 300 seconds",
+                "* You are muted for another 300 seconds",
+                "* The room contains: alice"
+            ]
+        );
+
+        rooms.line(alice, "/kick bob".to_string());
+        assert!(rooms.find("bob").is_none());
+        assert_eq!(
+            recv_all(&mut bob_rx).await,
+            vec!["* You were kicked by alice"]
+        );
+        assert_eq!(bob_rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_same_order() {
+        let room = RoomHandle::spawn(
+            ChatConfig::default(),
+            History::default(),
+            Transcript::default(),
+        );
+
+        let (watcher, mut watcher_rx) = room.join("watcher".to_string(), None).await.unwrap();
+        let (other, mut other_rx) = room.join("other".to_string(), None).await.unwrap();
+        let mut senders = Vec::new();
+        for n in 0..8 {
+            senders.push(room.join(format!("sender{n}"), None).await.unwrap());
+        }
+
+        /* many senders at once, each from its own task */
+        let tasks: Vec<_> = senders
+            .iter()
+            .map(|(id, _)| {
+                let room = room.clone();
+                let id = *id;
+                tokio::spawn(async move {
+                    for n in 0..25 {
+                        room.line(id, n.to_string()).await;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        /* a round trip through the task, so everything before is handled */
+        room.leave(watcher).await;
+        room.leave(other).await;
+        let _ = room.join("sync".to_string(), None).await.unwrap();
+
+        let seen = recv_all(&mut watcher_rx).await;
+        let chat: Vec<_> = seen.iter().filter(|line| line.starts_with('[')).collect();
+        assert_eq!(chat.len(), 8 * 25);
+
+        /* each sender's lines in the order sent */
+        for n in 0..8 {
+            let prefix = format!("[sender{n}] ");
+            let lines: Vec<_> = chat
+                .iter()
+                .filter_map(|line| line.strip_prefix(&prefix))
+                .collect();
+            let expected: Vec<_> = (0..25).map(|n| n.to_string()).collect();
+            assert_eq!(lines, expected);
+        }
+
+        /* and the very same interleaving for everybody */
+        let other_seen = recv_all(&mut other_rx).await;
+        let other_chat: Vec<_> = other_seen
+            .iter()
+            .filter(|line| line.starts_with('['))
+            .collect();
+        assert_eq!(chat, other_chat);
+    }
+}
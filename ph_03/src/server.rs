@@ -0,0 +1,413 @@
+use crate::codec::{ChatCodec, Input, LinePolicy};
+use crate::command::Command;
+use crate::history::History;
+use crate::moderation::Moderation;
+use crate::queue::{SlowPolicy, DEFAULT_QUEUE_DEPTH};
+use crate::room::RoomHandle;
+use crate::transcript::{Rotation, Transcript};
+use crate::websocket;
+use futures::sink::{Sink, SinkExt};
+use futures::stream::{Stream, StreamExt};
+use ph_common::limits::Limits;
+use ph_common::tls::{self, Acceptor};
+use std::error::Error;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::net::TcpListener;
+use tokio::time::{sleep_until, timeout, Duration, Instant};
+use tokio_util::codec::Framed;
+
+/* where everybody starts, and the only room a plain client ever sees */
+pub const DEFAULT_ROOM: &str = "lobby";
+
+/* the spec requires names of at least 16 characters to be allowed */
+pub const MIN_NAME_LEN: usize = 16;
+pub const DEFAULT_MAX_NAME_LEN: usize = 32;
+
+/* a client that fell behind likely is not reading, so do not wait long on it */
+const NOTICE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/*
+#[derive(Debug)]
+enum PhMsgWrite {
+    WelcomeMsg,
+    RoomList(Vec<User>),
+    UserMsg(String),
+    UserEntered(User),
+    UserExited(User),
+}
+*/
+
+/* settings shared by all clients, fixed once the server runs */
+#[derive(Debug, Clone)]
+pub struct ChatConfig {
+    pub max_name_len: usize,
+    /* slash commands, off by default as the spec makes every line chat */
+    pub commands: bool,
+    /* lines queued for a client before the slow policy kicks in */
+    pub queue_depth: usize,
+    pub slow_policy: SlowPolicy,
+    pub lines: LinePolicy,
+    pub moderation: Moderation,
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        ChatConfig {
+            max_name_len: DEFAULT_MAX_NAME_LEN,
+            commands: false,
+            queue_depth: DEFAULT_QUEUE_DEPTH,
+            slow_policy: SlowPolicy::default(),
+            lines: LinePolicy::default(),
+            moderation: Moderation::default(),
+        }
+    }
+}
+
+fn validate(what: &str, name: &str, max_len: usize) -> Result<(), String> {
+    if name.is_empty() {
+        return Err(format!("{what} must not be empty"));
+    }
+    if !name.chars().all(char::is_alphanumeric) {
+        return Err(format!("{what} may only contain letters and digits"));
+    }
+    if name.chars().count() > max_len {
+        return Err(format!("{what} may be at most {max_len} characters long"));
+    }
+
+    Ok(())
+}
+
+/* the reason is sent to the client before disconnecting it */
+pub fn validate_name(name: &str, max_len: usize) -> Result<(), String> {
+    validate("Name", name, max_len)
+}
+
+/* room names follow the same rules as usernames */
+pub fn validate_room(name: &str, max_len: usize) -> Result<(), String> {
+    validate("Room name", name, max_len)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Transport {
+    Tcp,
+    WebSocket,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ChatServer {
+    config: ChatConfig,
+    history: usize,
+    history_dir: Option<PathBuf>,
+    transcript: Option<PathBuf>,
+    rotation: Rotation,
+    websocket: Option<String>,
+    acceptor: Acceptor,
+    limits: Limits,
+}
+
+impl ChatServer {
+    pub fn new() -> ChatServer {
+        ChatServer::default()
+    }
+
+    pub fn with_max_name_len(mut self, max_name_len: usize) -> ChatServer {
+        self.config.max_name_len = max_name_len.max(MIN_NAME_LEN);
+        self
+    }
+
+    pub fn with_commands(mut self, commands: bool) -> ChatServer {
+        self.config.commands = commands;
+        self
+    }
+
+    pub fn with_queue_depth(mut self, queue_depth: usize) -> ChatServer {
+        self.config.queue_depth = queue_depth;
+        self
+    }
+
+    pub fn with_slow_policy(mut self, slow_policy: SlowPolicy) -> ChatServer {
+        self.config.slow_policy = slow_policy;
+        self
+    }
+
+    /* replay the last lines of a room to whoever joins it, 0 to disable */
+    pub fn with_history(mut self, history: usize) -> ChatServer {
+        self.history = history;
+        self
+    }
+
+    /* keep the history in this directory across restarts */
+    pub fn with_history_dir(mut self, path: Option<PathBuf>) -> ChatServer {
+        self.history_dir = path;
+        self
+    }
+
+    /* log joins, leaves and messages to this file as JSON Lines */
+    pub fn with_transcript(mut self, path: Option<PathBuf>) -> ChatServer {
+        self.transcript = path;
+        self
+    }
+
+    pub fn with_transcript_rotation(mut self, rotation: Rotation) -> ChatServer {
+        self.rotation = rotation;
+        self
+    }
+
+    /* what to do about over-long lines and invalid UTF-8 */
+    pub fn with_line_policy(mut self, lines: LinePolicy) -> ChatServer {
+        self.config.lines = lines;
+        self
+    }
+
+    /* banned words, admins and flood protection */
+    pub fn with_moderation(mut self, moderation: Moderation) -> ChatServer {
+        self.config.moderation = moderation;
+        self
+    }
+
+    /* also accept WebSocket clients, one text frame per line, on this address */
+    pub fn with_websocket(mut self, hostname: Option<String>) -> ChatServer {
+        self.websocket = hostname;
+        self
+    }
+
+    pub fn with_tls(mut self, acceptor: Acceptor) -> ChatServer {
+        self.acceptor = acceptor;
+        self
+    }
+
+    /* cap connections, throttle chatty clients and drop idle ones */
+    pub fn with_limits(mut self, limits: Limits) -> ChatServer {
+        self.limits = limits;
+        self
+    }
+
+    pub async fn run(self, hostname: String) -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind(hostname).await?;
+        let ws_listener = match &self.websocket {
+            Some(hostname) => Some(TcpListener::bind(hostname).await?),
+            None => None,
+        };
+        let history = match &self.history_dir {
+            Some(dir) if self.history > 0 => History::persistent(self.history, dir).await?,
+            _ => History::new(self.history),
+        };
+        let transcript = match &self.transcript {
+            Some(path) => Transcript::open(path, self.rotation).await?,
+            None => Transcript::default(),
+        };
+        let room = RoomHandle::spawn(self.config.clone(), history, transcript);
+
+        if let Some(ws_listener) = ws_listener {
+            let server = self.clone();
+            let room = room.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server.accept(ws_listener, Transport::WebSocket, room).await {
+                    println!("WebSocket listener failed: {e:?}");
+                }
+            });
+        }
+
+        self.accept(listener, Transport::Tcp, room).await
+    }
+
+    async fn accept(
+        &self,
+        listener: TcpListener,
+        transport: Transport,
+        room: RoomHandle,
+    ) -> Result<(), Box<dyn Error>> {
+        loop {
+            let (stream, addr) = listener.accept().await?;
+            let guard = match self.limits.acquire(addr.ip()) {
+                Some(guard) => guard,
+                None => {
+                    println!(
+                        "Rejected connection from {addr}, {} rejected so far",
+                        self.limits.rejected()
+                    );
+                    continue;
+                }
+            };
+            let limits = self.limits.clone();
+            let room = room.clone();
+            let acceptor = self.acceptor.clone();
+            let config = self.config.clone();
+
+            tokio::spawn(async move {
+                let _guard = guard;
+                let stream = match acceptor.accept(stream).await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        println!("TLS handshake failed: {e:?}");
+                        return;
+                    }
+                };
+
+                let result = match transport {
+                    Transport::Tcp => handle_tcp(stream, addr, room, config, limits).await,
+                    Transport::WebSocket => {
+                        websocket::handle(stream, addr, room, config, limits).await
+                    }
+                };
+                if let Err(e) = result {
+                    println!("Error occurred: {e:?}");
+                }
+            });
+        }
+    }
+}
+
+async fn handle_tcp(
+    stream: tls::Stream,
+    addr: SocketAddr,
+    room: RoomHandle,
+    config: ChatConfig,
+    limits: Limits,
+) -> Result<(), Box<dyn Error>> {
+    let codec = Framed::new(limits.wrap(stream), ChatCodec::new(config.lines.clone()));
+    let (writer, reader) = codec.split();
+
+    handle_client(reader, writer, addr, room, config, limits).await
+}
+
+/* the chat session, whichever way the lines come in */
+pub async fn handle_client<R, W>(
+    mut reader: R,
+    mut writer: W,
+    addr: SocketAddr,
+    room: RoomHandle,
+    config: ChatConfig,
+    limits: Limits,
+) -> Result<(), Box<dyn Error>>
+where
+    R: Stream<Item = io::Result<Input>> + Unpin,
+    W: Sink<String, Error = io::Error> + Unpin,
+{
+    println!("New connection: {addr}");
+
+    let mut rate_limiter = limits.rate_limiter();
+
+    writer
+        .send("Welcome! What is your name?".to_string())
+        .await?;
+
+    let line = match reader.next().await {
+        Some(Ok(input)) => match config.lines.notice(&input) {
+            Some(notice) => {
+                println!("Invalid username, abort");
+                writer.send(notice).await?;
+                return Ok(());
+            }
+            None => input.into_line().unwrap_or_default(),
+        },
+        _ => {
+            println!("Failed to read username");
+            return Ok(());
+        }
+    };
+
+    /* an admin follows their name with the admin token, kept out of the logs */
+    let (username, token) = match line.split_once(' ') {
+        Some((name, token)) if config.moderation.is_admin(name) => {
+            (name.to_string(), Some(token.to_string()))
+        }
+        _ => (line, None),
+    };
+    let (id, mut rx) = match room.join(username.clone(), token).await {
+        Ok(joined) => joined,
+        Err(reason) => {
+            println!("Refused username {username:?}: {reason}, abort");
+            writer.send(format!("* {reason}")).await?;
+            return Ok(());
+        }
+    };
+
+    let mut violations = 0;
+    /* over the rate, reading pauses while the chat still goes out */
+    let mut resume_at = None;
+    loop {
+        tokio::select! {
+             msg = rx.recv() => match msg {
+                 Some(msg) => {
+                     println!("Sending: {msg}");
+                     writer.send(msg).await?;
+                 }
+                 None => {
+                     if rx.overflowed() {
+                         println!("Client {username} fell behind, disconnecting");
+                         let notice = "* Disconnected for not keeping up with the chat";
+                         let _ = timeout(NOTICE_TIMEOUT, writer.send(notice.to_string())).await;
+                     }
+                     break;
+                 }
+             },
+             _ = sleep_until(resume_at.unwrap_or_else(Instant::now)), if resume_at.is_some() => {
+                 resume_at = None;
+             },
+             result = reader.next(), if resume_at.is_none() => match result {
+                 Some(Ok(input)) => {
+                     resume_at = rate_limiter.resume_at();
+
+                     if let Some(notice) = config.lines.notice(&input) {
+                         println!("Violation by {username}: {:?}", input.violation());
+                         writer.send(notice).await?;
+
+                         violations += 1;
+                         if violations == config.lines.max_violations {
+                             let notice = "* Too many invalid lines, disconnecting";
+                             writer.send(notice.to_string()).await?;
+                             break;
+                         }
+                     }
+                     let Some(msg) = input.into_line() else {
+                         continue;
+                     };
+                     println!("Received: {msg}");
+
+                     if config.commands && Command::parse(&msg) == Some(Command::Quit) {
+                         break;
+                     }
+                     room.line(id, msg).await;
+                 },
+                 Some(Err(e)) => {
+                     println!("Error: {e:?}");
+                     break;
+                 }
+                 None => break,
+             },
+        }
+    }
+
+    println!("Closing connection");
+    room.leave(id).await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("alice", 16).is_ok());
+        assert!(validate_name("Bob1984", 16).is_ok());
+        assert!(validate_name(&"a".repeat(16), 16).is_ok());
+
+        assert_eq!(
+            validate_name("", 16),
+            Err("Name must not be empty".to_string())
+        );
+        assert_eq!(
+            validate_name("bob!", 16),
+            Err("Name may only contain letters and digits".to_string())
+        );
+        assert_eq!(
+            validate_name(&"a".repeat(17), 16),
+            Err("Name may be at most 16 characters long".to_string())
+        );
+    }
+}
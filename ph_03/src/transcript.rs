@@ -0,0 +1,297 @@
+use serde::Serialize;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+pub const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+pub const DEFAULT_KEEP: usize = 5;
+/* records waiting for the disk, any more are dropped and counted */
+const QUEUE_LEN: usize = 4096;
+
+/* once the file would grow past max_bytes it becomes `{path}.1`, and so on */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    pub max_bytes: u64,
+    /* rotated files kept around, the oldest beyond that is deleted */
+    pub keep: usize,
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Rotation {
+            max_bytes: DEFAULT_MAX_BYTES,
+            keep: DEFAULT_KEEP,
+        }
+    }
+}
+
+/* what happened in the chat, text as the other users got to see it */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Join {
+        user: &'a str,
+        room: &'a str,
+    },
+    Leave {
+        user: &'a str,
+        room: &'a str,
+    },
+    Message {
+        user: &'a str,
+        room: &'a str,
+        text: &'a str,
+    },
+    /* from /me */
+    Action {
+        user: &'a str,
+        room: &'a str,
+        text: &'a str,
+    },
+    Private {
+        user: &'a str,
+        to: &'a str,
+        text: &'a str,
+    },
+    Nick {
+        user: &'a str,
+        nick: &'a str,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct Record<'a> {
+    time: String,
+    #[serde(flatten)]
+    event: Event<'a>,
+}
+
+/*
+ * Every join, leave and message as one JSON object per line. The events are
+ * timestamped and serialized right away, in chat order, and written out by a
+ * task of their own so the room never waits on the disk. Should the disk fall
+ * too far behind, records are dropped rather than piling up in memory. The
+ * default does not record anything.
+ */
+#[derive(Debug, Default, Clone)]
+pub struct Transcript {
+    tx: Option<mpsc::Sender<String>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Transcript {
+    fn channel(len: usize) -> (Transcript, mpsc::Receiver<String>) {
+        let (tx, rx) = mpsc::channel(len);
+        let transcript = Transcript {
+            tx: Some(tx),
+            dropped: Arc::default(),
+        };
+
+        (transcript, rx)
+    }
+
+    /* appends to whatever an earlier run left in the file */
+    pub async fn open(path: impl Into<PathBuf>, rotation: Rotation) -> io::Result<Transcript> {
+        let mut log = Log::open(path.into(), rotation).await?;
+        let (transcript, mut rx) = Transcript::channel(QUEUE_LEN);
+
+        tokio::spawn(async move {
+            while let Some(line) = rx.recv().await {
+                if let Err(e) = log.write(&line).await {
+                    println!("Failed to write transcript: {e:?}");
+                }
+            }
+        });
+
+        Ok(transcript)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.tx.is_some()
+    }
+
+    pub fn record(&self, event: Event) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+
+        let record = Record {
+            time: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            event,
+        };
+        let line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                println!("Failed to serialize transcript record: {e:?}");
+                return;
+            }
+        };
+        if tx.try_send(line).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped.is_power_of_two() {
+                println!("Transcript falling behind, {dropped} records dropped");
+            }
+        }
+    }
+
+    /* records dropped because the writer task fell behind */
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/* the file itself, only touched by the writer task */
+#[derive(Debug)]
+struct Log {
+    path: PathBuf,
+    rotation: Rotation,
+    file: File,
+    written: u64,
+}
+
+impl Log {
+    async fn open(path: PathBuf, rotation: Rotation) -> io::Result<Log> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        let written = file.metadata().await?.len();
+
+        Ok(Log {
+            path,
+            rotation,
+            file,
+            written,
+        })
+    }
+
+    async fn write(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        /* a line longer than max_bytes still gets a file of its own */
+        if self.written > 0 && self.written + len > self.rotation.max_bytes {
+            self.rotate().await?;
+        }
+
+        self.file.write_all(format!("{line}\n").as_bytes()).await?;
+        self.file.flush().await?;
+        self.written += len;
+
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> io::Result<()> {
+        for n in (1..self.rotation.keep).rev() {
+            rename_if_exists(&rotated(&self.path, n), &rotated(&self.path, n + 1)).await?;
+        }
+        if self.rotation.keep > 0 {
+            tokio::fs::rename(&self.path, rotated(&self.path, 1)).await?;
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)
+            .await?;
+        self.written = 0;
+
+        Ok(())
+    }
+}
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    name.into()
+}
+
+async fn rename_if_exists(from: &Path, to: &Path) -> io::Result<()> {
+    match tokio::fs::rename(from, to).await {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let record = Record {
+            time: "2024-01-01T00:00:00.000Z".to_string(),
+            event: Event::Message {
+                user: "bob",
+                room: "lobby",
+                text: "say \"hi\"",
+            },
+        };
+
+        assert_eq!(
+            serde_json::to_string(&record).unwrap(),
+            r#"{"time":"2024-01-01T00:00:00.000Z","event":"message","user":"bob","room":"lobby","text":"say \"hi\""}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_queue_full() {
+        let (transcript, mut rx) = Transcript::channel(2);
+        for user in ["alice", "bob", "carol"] {
+            transcript.record(Event::Join {
+                user,
+                room: "lobby",
+            });
+        }
+
+        /* nothing is writing, the third record is dropped, not queued */
+        assert_eq!(transcript.dropped(), 1);
+        assert!(rx.recv().await.unwrap().contains("\"user\":\"alice\""));
+        assert!(rx.recv().await.unwrap().contains("\"user\":\"bob\""));
+        assert!(rx.try_recv().is_err());
+
+        /* once there is room again records are queued as before */
+        transcript.record(Event::Leave {
+            user: "alice",
+            room: "lobby",
+        });
+        assert!(rx.recv().await.unwrap().contains("\"event\":\"leave\""));
+        assert_eq!(transcript.dropped(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chat.jsonl");
+        let rotation = Rotation {
+            max_bytes: 8,
+            keep: 2,
+        };
+
+        let mut log = Log::open(path.clone(), rotation).await.unwrap();
+        for line in ["one", "two", "three", "four", "five"] {
+            log.write(line).await.unwrap();
+        }
+
+        let read = |path: PathBuf| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(path.clone()), "five\n");
+        assert_eq!(read(rotated(&path, 1)), "four\n");
+        assert_eq!(read(rotated(&path, 2)), "three\n");
+        assert!(!rotated(&path, 3).exists());
+
+        /* a restart carries on with the same file */
+        let mut log = Log::open(path.clone(), rotation).await.unwrap();
+        log.write("six").await.unwrap();
+        assert_eq!(read(path.clone()), "six\n");
+        assert_eq!(read(rotated(&path, 1)), "five\n");
+        assert_eq!(read(rotated(&path, 2)), "four\n");
+    }
+}
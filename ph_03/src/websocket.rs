@@ -0,0 +1,123 @@
+use bytes::BytesMut;
+use futures::future;
+use futures::sink::SinkExt;
+use futures::stream::StreamExt;
+use ph_common::limits::Limits;
+use ph_common::tls::Stream;
+use std::error::Error;
+use std::io;
+use std::net::SocketAddr;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_util::codec::Decoder;
+
+use crate::codec::{ChatCodec, Input};
+use crate::room::RoomHandle;
+use crate::server::{handle_client, ChatConfig};
+
+/* lets a frame somewhat over the line limit reach the line policy */
+const FRAME_HEADROOM: usize = 4096;
+
+/*
+ * Anything bigger than a line plus headroom is refused from the frame header,
+ * before it is buffered, and ends the connection.
+ */
+fn ws_config(config: &ChatConfig) -> WebSocketConfig {
+    let max_size = config.lines.max_len.saturating_add(FRAME_HEADROOM);
+
+    WebSocketConfig {
+        max_message_size: Some(max_size),
+        max_frame_size: Some(max_size),
+        ..Default::default()
+    }
+}
+
+/*
+ * One frame is one line, so newlines inside it are flattened rather than
+ * letting a frame pass for several lines. Length and UTF-8 (for binary
+ * frames) are checked like any line from a TCP client.
+ */
+fn frame_input(codec: &mut ChatCodec, mut data: Vec<u8>) -> io::Result<Input> {
+    for byte in data.iter_mut() {
+        if *byte == b'\n' || *byte == b'\r' {
+            *byte = b' ';
+        }
+    }
+    data.push(b'\n');
+
+    let mut buf = BytesMut::from(&data[..]);
+    codec
+        .decode(&mut buf)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Incomplete frame"))
+}
+
+/* a browser joining the chat, one text frame per line both ways */
+pub async fn handle(
+    stream: Stream,
+    addr: SocketAddr,
+    room: RoomHandle,
+    config: ChatConfig,
+    limits: Limits,
+) -> Result<(), Box<dyn Error>> {
+    let ws =
+        tokio_tungstenite::accept_async_with_config(limits.wrap(stream), Some(ws_config(&config)))
+            .await?;
+    let (sink, stream) = ws.split();
+
+    let writer = sink
+        .sink_map_err(io::Error::other)
+        .with(|line: String| future::ok::<_, io::Error>(Message::Text(line)));
+
+    let mut codec = ChatCodec::new(config.lines.clone());
+    let reader = stream.filter_map(move |msg| {
+        let input = match msg {
+            Ok(Message::Text(text)) => Some(frame_input(&mut codec, text.into_bytes())),
+            Ok(Message::Binary(data)) => Some(frame_input(&mut codec, data)),
+            /* pings are answered by tungstenite, a close ends the stream */
+            Ok(_) => None,
+            Err(e) => Some(Err(io::Error::other(e))),
+        };
+        future::ready(input)
+    });
+
+    handle_client(
+        Box::pin(reader),
+        Box::pin(writer),
+        addr,
+        room,
+        config,
+        limits,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{LinePolicy, Violation};
+
+    #[test]
+    fn test_frame_input() {
+        let mut codec = ChatCodec::new(LinePolicy {
+            max_len: 8,
+            ..Default::default()
+        });
+
+        assert_eq!(
+            frame_input(&mut codec, b"hello".to_vec()).unwrap(),
+            Input::Line("hello".to_string())
+        );
+        assert_eq!(
+            frame_input(&mut codec, b"a\nb\r\n".to_vec()).unwrap(),
+            Input::Line("a b  ".to_string())
+        );
+        assert_eq!(
+            frame_input(&mut codec, b"0123456789".to_vec()).unwrap(),
+            Input::Repaired("01234567".to_string(), Violation::TooLong)
+        );
+        assert_eq!(
+            frame_input(&mut codec, b"caf\xe9".to_vec()).unwrap(),
+            Input::Repaired("caf\u{fffd}".to_string(), Violation::InvalidUtf8)
+        );
+    }
+}
@@ -0,0 +1,848 @@
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::time::{sleep, timeout, Duration};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use ph_03::codec::{InvalidUtf8, LinePolicy, LongLines};
+use ph_03::moderation::{FloodLimit, Moderation};
+use ph_03::queue::SlowPolicy;
+use ph_03::server::ChatServer;
+use ph_03::transcript::Rotation;
+
+async fn spawn_app(port: u16, server: ChatServer) -> String {
+    let hostname = format!("127.0.0.1:{port}");
+    let listen = hostname.clone();
+    tokio::spawn(async move {
+        let _ = server.run(listen).await;
+    });
+    sleep(Duration::from_millis(100)).await;
+
+    hostname
+}
+
+struct ChatClient {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl ChatClient {
+    /* connects and answers the name prompt */
+    async fn connect(hostname: &str, name: &str) -> ChatClient {
+        let stream = TcpStream::connect(hostname).await.unwrap();
+        let (reader, writer) = stream.into_split();
+        let mut client = ChatClient {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        };
+
+        assert_eq!(client.recv().await.unwrap(), "Welcome! What is your name?");
+        client.send(name).await;
+
+        client
+    }
+
+    async fn send(&mut self, line: &str) {
+        self.writer
+            .write_all(format!("{line}\n").as_bytes())
+            .await
+            .unwrap();
+    }
+
+    /* None once the server closed the connection */
+    async fn recv(&mut self) -> Option<String> {
+        timeout(Duration::from_secs(5), self.lines.next_line())
+            .await
+            .expect("Timed out waiting for a line")
+            .unwrap()
+    }
+}
+
+#[tokio::test]
+async fn test_chat() {
+    let hostname = spawn_app(7777, ChatServer::new()).await;
+
+    let mut alice = ChatClient::connect(&hostname, "alice").await;
+    assert_eq!(alice.recv().await.unwrap(), "* The room contains: ");
+
+    let mut bob = ChatClient::connect(&hostname, "bob").await;
+    assert_eq!(bob.recv().await.unwrap(), "* The room contains: alice");
+    assert_eq!(alice.recv().await.unwrap(), "* bob has entered the room");
+
+    bob.send("hi alice").await;
+    assert_eq!(alice.recv().await.unwrap(), "[bob] hi alice");
+
+    drop(bob);
+    assert_eq!(alice.recv().await.unwrap(), "* bob has left the room");
+}
+
+#[tokio::test]
+async fn test_duplicate_name() {
+    let hostname = spawn_app(7778, ChatServer::new()).await;
+
+    let mut alice = ChatClient::connect(&hostname, "alice").await;
+    assert_eq!(alice.recv().await.unwrap(), "* The room contains: ");
+
+    let mut impostor = ChatClient::connect(&hostname, "alice").await;
+    assert_eq!(
+        impostor.recv().await.unwrap(),
+        "* Name alice is already taken"
+    );
+    assert_eq!(impostor.recv().await, None);
+
+    /* the first alice still gets messages */
+    let mut bob = ChatClient::connect(&hostname, "bob").await;
+    assert_eq!(bob.recv().await.unwrap(), "* The room contains: alice");
+    assert_eq!(alice.recv().await.unwrap(), "* bob has entered the room");
+
+    bob.send("still there?").await;
+    assert_eq!(alice.recv().await.unwrap(), "[bob] still there?");
+}
+
+#[tokio::test]
+async fn test_name_length() {
+    let hostname = spawn_app(7779, ChatServer::new().with_max_name_len(20)).await;
+
+    let mut client = ChatClient::connect(&hostname, &"a".repeat(21)).await;
+    assert_eq!(
+        client.recv().await.unwrap(),
+        "* Name may be at most 20 characters long"
+    );
+    assert_eq!(client.recv().await, None);
+
+    let mut client = ChatClient::connect(&hostname, &"a".repeat(20)).await;
+    assert_eq!(client.recv().await.unwrap(), "* The room contains: ");
+}
+
+#[tokio::test]
+async fn test_invalid_name() {
+    let hostname = spawn_app(7780, ChatServer::new()).await;
+
+    let mut client = ChatClient::connect(&hostname, "bad name").await;
+    assert_eq!(
+        client.recv().await.unwrap(),
+        "* Name may only contain letters and digits"
+    );
+    assert_eq!(client.recv().await, None);
+}
+
+#[tokio::test]
+async fn test_rooms() {
+    let hostname = spawn_app(7781, ChatServer::new().with_commands(true)).await;
+
+    let mut alice = ChatClient::connect(&hostname, "alice").await;
+    assert_eq!(alice.recv().await.unwrap(), "* The room contains: ");
+    let mut bob = ChatClient::connect(&hostname, "bob").await;
+    assert_eq!(bob.recv().await.unwrap(), "* The room contains: alice");
+    assert_eq!(alice.recv().await.unwrap(), "* bob has entered the room");
+    let mut carol = ChatClient::connect(&hostname, "carol").await;
+    assert_eq!(
+        carol.recv().await.unwrap(),
+        "* The room contains: alice, bob"
+    );
+    assert_eq!(alice.recv().await.unwrap(), "* carol has entered the room");
+    assert_eq!(bob.recv().await.unwrap(), "* carol has entered the room");
+
+    bob.send("/join rust").await;
+    assert_eq!(bob.recv().await.unwrap(), "* The room contains: ");
+    assert_eq!(alice.recv().await.unwrap(), "* bob has left the room");
+    assert_eq!(carol.recv().await.unwrap(), "* bob has left the room");
+
+    carol.send("/join rust").await;
+    assert_eq!(carol.recv().await.unwrap(), "* The room contains: bob");
+    assert_eq!(alice.recv().await.unwrap(), "* carol has left the room");
+    assert_eq!(bob.recv().await.unwrap(), "* carol has entered the room");
+
+    /* chat stays within the room */
+    bob.send("only for rust").await;
+    assert_eq!(carol.recv().await.unwrap(), "[bob] only for rust");
+    alice.send("anybody?").await;
+    carol.send("/who").await;
+    assert_eq!(carol.recv().await.unwrap(), "* The room contains: bob");
+
+    alice.send("/rooms").await;
+    assert_eq!(alice.recv().await.unwrap(), "* Rooms: lobby (1), rust (2)");
+
+    drop(carol);
+    assert_eq!(bob.recv().await.unwrap(), "* carol has left the room");
+
+    bob.send("/leave").await;
+    assert_eq!(bob.recv().await.unwrap(), "* The room contains: alice");
+    assert_eq!(alice.recv().await.unwrap(), "* bob has entered the room");
+
+    /* empty rooms are gone */
+    bob.send("/rooms").await;
+    assert_eq!(bob.recv().await.unwrap(), "* Rooms: lobby (2)");
+}
+
+#[tokio::test]
+async fn test_room_errors() {
+    let hostname = spawn_app(7782, ChatServer::new().with_commands(true)).await;
+
+    let mut alice = ChatClient::connect(&hostname, "alice").await;
+    assert_eq!(alice.recv().await.unwrap(), "* The room contains: ");
+
+    alice.send("/leave").await;
+    assert_eq!(alice.recv().await.unwrap(), "* You are already in lobby");
+    alice.send("/join").await;
+    assert_eq!(alice.recv().await.unwrap(), "* Room name must not be empty");
+    alice.send("/join no way").await;
+    assert_eq!(
+        alice.recv().await.unwrap(),
+        "* Room name may only contain letters and digits"
+    );
+    alice.send("/join lobby").await;
+    assert_eq!(alice.recv().await.unwrap(), "* You are already in lobby");
+
+    /* unknown commands are plain chat */
+    let mut bob = ChatClient::connect(&hostname, "bob").await;
+    assert_eq!(bob.recv().await.unwrap(), "* The room contains: alice");
+    assert_eq!(alice.recv().await.unwrap(), "* bob has entered the room");
+    alice.send("/shrug").await;
+    assert_eq!(bob.recv().await.unwrap(), "[alice] /shrug");
+}
+
+#[tokio::test]
+async fn test_commands_disabled() {
+    let hostname = spawn_app(7783, ChatServer::new()).await;
+
+    let mut alice = ChatClient::connect(&hostname, "alice").await;
+    assert_eq!(alice.recv().await.unwrap(), "* The room contains: ");
+    let mut bob = ChatClient::connect(&hostname, "bob").await;
+    assert_eq!(bob.recv().await.unwrap(), "* The room contains: alice");
+    assert_eq!(alice.recv().await.unwrap(), "* bob has entered the room");
+
+    for line in ["/join rust", "/msg alice hi", "/nick bobby", "/quit"] {
+        bob.send(line).await;
+        assert_eq!(alice.recv().await.unwrap(), format!("[bob] {line}"));
+    }
+}
+
+#[tokio::test]
+async fn test_private_messages() {
+    let hostname = spawn_app(7784, ChatServer::new().with_commands(true)).await;
+
+    let mut alice = ChatClient::connect(&hostname, "alice").await;
+    assert_eq!(alice.recv().await.unwrap(), "* The room contains: ");
+    let mut bob = ChatClient::connect(&hostname, "bob").await;
+    assert_eq!(bob.recv().await.unwrap(), "* The room contains: alice");
+    assert_eq!(alice.recv().await.unwrap(), "* bob has entered the room");
+    let mut carol = ChatClient::connect(&hostname, "carol").await;
+    assert_eq!(
+        carol.recv().await.unwrap(),
+        "* The room contains: alice, bob"
+    );
+    assert_eq!(alice.recv().await.unwrap(), "* carol has entered the room");
+    assert_eq!(bob.recv().await.unwrap(), "* carol has entered the room");
+
+    /* across rooms, and nobody else hears it */
+    carol.send("/join rust").await;
+    assert_eq!(carol.recv().await.unwrap(), "* The room contains: ");
+    assert_eq!(alice.recv().await.unwrap(), "* carol has left the room");
+    assert_eq!(bob.recv().await.unwrap(), "* carol has left the room");
+
+    alice.send("/msg carol psst").await;
+    assert_eq!(alice.recv().await.unwrap(), "[alice -> carol] psst");
+    assert_eq!(carol.recv().await.unwrap(), "[alice -> carol] psst");
+
+    alice.send("/msg dave hello").await;
+    assert_eq!(alice.recv().await.unwrap(), "* No user named dave");
+    alice.send("/msg carol").await;
+    assert_eq!(alice.recv().await.unwrap(), "* Usage: /msg <user> <text>");
+
+    alice.send("/me waves").await;
+    assert_eq!(bob.recv().await.unwrap(), "* alice waves");
+
+    bob.send("public").await;
+    assert_eq!(alice.recv().await.unwrap(), "[bob] public");
+}
+
+#[tokio::test]
+async fn test_nick_and_quit() {
+    let server = ChatServer::new().with_commands(true).with_max_name_len(16);
+    let hostname = spawn_app(7785, server).await;
+
+    let mut alice = ChatClient::connect(&hostname, "alice").await;
+    assert_eq!(alice.recv().await.unwrap(), "* The room contains: ");
+    let mut bob = ChatClient::connect(&hostname, "bob").await;
+    assert_eq!(bob.recv().await.unwrap(), "* The room contains: alice");
+    assert_eq!(alice.recv().await.unwrap(), "* bob has entered the room");
+
+    /* the same checks as when joining */
+    bob.send("/nick alice").await;
+    assert_eq!(bob.recv().await.unwrap(), "* Name alice is already taken");
+    bob.send("/nick bob!").await;
+    assert_eq!(
+        bob.recv().await.unwrap(),
+        "* Name may only contain letters and digits"
+    );
+    bob.send(&format!("/nick {}", "b".repeat(17))).await;
+    assert_eq!(
+        bob.recv().await.unwrap(),
+        "* Name may be at most 16 characters long"
+    );
+
+    bob.send("/nick robert").await;
+    assert_eq!(bob.recv().await.unwrap(), "* You are now known as robert");
+    assert_eq!(alice.recv().await.unwrap(), "* bob is now known as robert");
+
+    bob.send("hello").await;
+    assert_eq!(alice.recv().await.unwrap(), "[robert] hello");
+
+    /* the old name is free again */
+    let mut other = ChatClient::connect(&hostname, "bob").await;
+    assert_eq!(
+        other.recv().await.unwrap(),
+        "* The room contains: alice, robert"
+    );
+    assert_eq!(alice.recv().await.unwrap(), "* bob has entered the room");
+    assert_eq!(bob.recv().await.unwrap(), "* bob has entered the room");
+
+    bob.send("/quit").await;
+    assert_eq!(bob.recv().await, None);
+    assert_eq!(alice.recv().await.unwrap(), "* robert has left the room");
+}
+
+/* joins like any client, then never reads again */
+async fn stalled_client(hostname: &str, name: &str) -> TcpStream {
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.set_recv_buffer_size(4096).unwrap();
+    let mut stream = socket.connect(hostname.parse().unwrap()).await.unwrap();
+    stream
+        .write_all(format!("{name}\n").as_bytes())
+        .await
+        .unwrap();
+
+    stream
+}
+
+const FLOOD_LINES: usize = 5000;
+
+async fn flood(client: &mut ChatClient) {
+    let line = "x".repeat(1000);
+    let payload: String = (0..FLOOD_LINES).map(|n| format!("{n} {line}\n")).collect();
+    client.writer.write_all(payload.as_bytes()).await.unwrap();
+    client.send("done").await;
+}
+
+#[tokio::test]
+async fn test_slow_consumer_disconnected() {
+    let server = ChatServer::new()
+        .with_queue_depth(16)
+        .with_slow_policy(SlowPolicy::Disconnect)
+        .with_commands(true);
+    let hostname = spawn_app(7786, server).await;
+
+    let mut alice = ChatClient::connect(&hostname, "alice").await;
+    assert_eq!(alice.recv().await.unwrap(), "* The room contains: ");
+    let _bob = stalled_client(&hostname, "bob").await;
+    assert_eq!(alice.recv().await.unwrap(), "* bob has entered the room");
+
+    /* answered only once the server went through the whole flood */
+    flood(&mut alice).await;
+    alice.send("/who").await;
+    let mut replies = vec![alice.recv().await.unwrap(), alice.recv().await.unwrap()];
+    replies.sort();
+    assert_eq!(
+        replies,
+        vec!["* The room contains: ", "* bob has left the room"]
+    );
+
+    /* everybody else carries on */
+    let mut carol = ChatClient::connect(&hostname, "carol").await;
+    assert_eq!(carol.recv().await.unwrap(), "* The room contains: alice");
+    alice.send("still here").await;
+    assert_eq!(carol.recv().await.unwrap(), "[alice] still here");
+}
+
+#[tokio::test]
+async fn test_slow_consumer_drop_oldest() {
+    let server = ChatServer::new()
+        .with_queue_depth(16)
+        .with_slow_policy(SlowPolicy::DropOldest)
+        .with_commands(true);
+    let hostname = spawn_app(7787, server).await;
+
+    let mut alice = ChatClient::connect(&hostname, "alice").await;
+    assert_eq!(alice.recv().await.unwrap(), "* The room contains: ");
+    let bob = stalled_client(&hostname, "bob").await;
+    assert_eq!(alice.recv().await.unwrap(), "* bob has entered the room");
+
+    flood(&mut alice).await;
+    alice.send("/who").await;
+    assert_eq!(alice.recv().await.unwrap(), "* The room contains: bob");
+
+    /* bob catches up, having missed what did not fit */
+    let mut lines = BufReader::new(bob).lines();
+    let mut received = 0;
+    loop {
+        let line = timeout(Duration::from_secs(5), lines.next_line())
+            .await
+            .expect("Timed out waiting for a line")
+            .unwrap()
+            .unwrap();
+        if line == "[alice] done" {
+            break;
+        }
+        received += 1;
+    }
+    assert!(received < FLOOD_LINES, "received all {received} lines");
+}
+
+#[tokio::test]
+async fn test_history() {
+    let server = ChatServer::new().with_history(2).with_commands(true);
+    let hostname = spawn_app(7788, server).await;
+
+    let mut alice = ChatClient::connect(&hostname, "alice").await;
+    assert_eq!(alice.recv().await.unwrap(), "* The room contains: ");
+    let mut bob = ChatClient::connect(&hostname, "bob").await;
+    assert_eq!(bob.recv().await.unwrap(), "* The room contains: alice");
+    assert_eq!(alice.recv().await.unwrap(), "* bob has entered the room");
+
+    for line in ["one", "two", "three"] {
+        alice.send(line).await;
+        assert_eq!(bob.recv().await.unwrap(), format!("[alice] {line}"));
+    }
+    bob.send("/me nods").await;
+    assert_eq!(alice.recv().await.unwrap(), "* bob nods");
+
+    /* only chat is kept, not presence notices */
+    let mut carol = ChatClient::connect(&hostname, "carol").await;
+    assert_eq!(
+        carol.recv().await.unwrap(),
+        "* The room contains: alice, bob"
+    );
+    assert_eq!(carol.recv().await.unwrap(), "[alice] three");
+    assert_eq!(carol.recv().await.unwrap(), "* bob nods");
+
+    /* every room has its own */
+    carol.send("/join rust").await;
+    assert_eq!(carol.recv().await.unwrap(), "* The room contains: ");
+    carol.send("anyone into rust?").await;
+    carol.send("/leave").await;
+    assert_eq!(
+        carol.recv().await.unwrap(),
+        "* The room contains: alice, bob"
+    );
+    assert_eq!(carol.recv().await.unwrap(), "[alice] three");
+    assert_eq!(carol.recv().await.unwrap(), "* bob nods");
+
+    for line in ["entered", "left", "entered"] {
+        assert_eq!(
+            bob.recv().await.unwrap(),
+            format!("* carol has {line} the room")
+        );
+    }
+    bob.send("/join rust").await;
+    assert_eq!(bob.recv().await.unwrap(), "* The room contains: ");
+    assert_eq!(bob.recv().await.unwrap(), "[carol] anyone into rust?");
+}
+
+#[tokio::test]
+async fn test_history_persisted() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let server = ChatServer::new()
+        .with_history(10)
+        .with_history_dir(Some(dir.path().to_path_buf()));
+    let hostname = spawn_app(7789, server).await;
+
+    let mut alice = ChatClient::connect(&hostname, "alice").await;
+    assert_eq!(alice.recv().await.unwrap(), "* The room contains: ");
+    let mut bob = ChatClient::connect(&hostname, "bob").await;
+    assert_eq!(bob.recv().await.unwrap(), "* The room contains: alice");
+    alice.send("remember me").await;
+    assert_eq!(bob.recv().await.unwrap(), "[alice] remember me");
+
+    /* a second server standing in for a restart */
+    let server = ChatServer::new()
+        .with_history(10)
+        .with_history_dir(Some(dir.path().to_path_buf()));
+    let hostname = spawn_app(7790, server).await;
+
+    let mut carol = ChatClient::connect(&hostname, "carol").await;
+    assert_eq!(carol.recv().await.unwrap(), "* The room contains: ");
+    assert_eq!(carol.recv().await.unwrap(), "[alice] remember me");
+}
+
+#[tokio::test]
+async fn test_concurrent_senders_same_order() {
+    let hostname = spawn_app(7791, ChatServer::new()).await;
+
+    let mut watchers = Vec::new();
+    for name in ["watcher1", "watcher2"] {
+        let mut watcher = ChatClient::connect(&hostname, name).await;
+        watcher.recv().await.unwrap();
+        watchers.push(watcher);
+    }
+    /* watcher1 sees watcher2 come in */
+    watchers[0].recv().await.unwrap();
+
+    let mut senders = Vec::new();
+    for n in 0..6 {
+        let mut sender = ChatClient::connect(&hostname, &format!("sender{n}")).await;
+        sender.recv().await.unwrap();
+        senders.push(sender);
+    }
+
+    let tasks: Vec<_> = senders
+        .into_iter()
+        .enumerate()
+        .map(|(n, mut sender)| {
+            tokio::spawn(async move {
+                for line in 0..30 {
+                    sender.send(&format!("{n}:{line}")).await;
+                }
+                sender
+            })
+        })
+        .collect();
+    let mut senders = Vec::new();
+    for task in tasks {
+        senders.push(task.await.unwrap());
+    }
+
+    let mut seen = Vec::new();
+    for watcher in watchers.iter_mut() {
+        let mut chat = Vec::new();
+        while chat.len() < 6 * 30 {
+            let line = watcher.recv().await.unwrap();
+            if line.starts_with('[') {
+                chat.push(line);
+            }
+        }
+        seen.push(chat);
+    }
+    assert_eq!(seen[0], seen[1]);
+
+    for n in 0..6 {
+        let prefix = format!("[sender{n}] {n}:");
+        let lines: Vec<_> = seen[0]
+            .iter()
+            .filter_map(|line| line.strip_prefix(&prefix))
+            .collect();
+        let expected: Vec<_> = (0..30).map(|line| line.to_string()).collect();
+        assert_eq!(lines, expected);
+    }
+}
+
+#[tokio::test]
+async fn test_long_line_truncated() {
+    let policy = LinePolicy {
+        max_len: 10,
+        ..Default::default()
+    };
+    let hostname = spawn_app(7792, ChatServer::new().with_line_policy(policy)).await;
+
+    let mut alice = ChatClient::connect(&hostname, "alice").await;
+    assert_eq!(alice.recv().await.unwrap(), "* The room contains: ");
+    let mut bob = ChatClient::connect(&hostname, "bob").await;
+    assert_eq!(bob.recv().await.unwrap(), "* The room contains: alice");
+    assert_eq!(alice.recv().await.unwrap(), "* bob has entered the room");
+
+    bob.send("0123456789abcdef").await;
+    assert_eq!(bob.recv().await.unwrap(), "* Line truncated to 10 bytes");
+    assert_eq!(alice.recv().await.unwrap(), "[bob] 0123456789");
+
+    /* lossily decoded, the line still gets through */
+    bob.writer.write_all(b"caf\xe9\n").await.unwrap();
+    assert_eq!(
+        bob.recv().await.unwrap(),
+        "* Invalid UTF-8 in line replaced"
+    );
+    assert_eq!(alice.recv().await.unwrap(), "[bob] caf\u{fffd}");
+
+    bob.send("fine").await;
+    assert_eq!(alice.recv().await.unwrap(), "[bob] fine");
+}
+
+#[tokio::test]
+async fn test_repeated_violations() {
+    let policy = LinePolicy {
+        max_len: 10,
+        long_lines: LongLines::Reject,
+        invalid_utf8: InvalidUtf8::Reject,
+        max_violations: 3,
+    };
+    let hostname = spawn_app(7793, ChatServer::new().with_line_policy(policy)).await;
+
+    let mut alice = ChatClient::connect(&hostname, "alice").await;
+    assert_eq!(alice.recv().await.unwrap(), "* The room contains: ");
+    let mut bob = ChatClient::connect(&hostname, "bob").await;
+    assert_eq!(bob.recv().await.unwrap(), "* The room contains: alice");
+    assert_eq!(alice.recv().await.unwrap(), "* bob has entered the room");
+
+    bob.send(&"x".repeat(5000)).await;
+    assert_eq!(
+        bob.recv().await.unwrap(),
+        "* Line longer than 10 bytes ignored"
+    );
+    bob.writer.write_all(b"\xff\xfe\n").await.unwrap();
+    assert_eq!(
+        bob.recv().await.unwrap(),
+        "* Line with invalid UTF-8 ignored"
+    );
+
+    /* nothing of the rejected lines reached the room */
+    bob.send("ok").await;
+    assert_eq!(alice.recv().await.unwrap(), "[bob] ok");
+
+    bob.send(&"x".repeat(11)).await;
+    assert_eq!(
+        bob.recv().await.unwrap(),
+        "* Line longer than 10 bytes ignored"
+    );
+    assert_eq!(
+        bob.recv().await.unwrap(),
+        "* Too many invalid lines, disconnecting"
+    );
+    assert_eq!(bob.recv().await, None);
+    assert_eq!(alice.recv().await.unwrap(), "* bob has left the room");
+}
+
+async fn ws_recv(ws: &mut WebSocketStream<MaybeTlsStream<TcpStream>>) -> String {
+    match timeout(Duration::from_secs(5), ws.next()).await.unwrap() {
+        Some(Ok(Message::Text(text))) => text,
+        other => panic!("Expected a text frame: {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_websocket() {
+    let server = ChatServer::new().with_websocket(Some("127.0.0.1:7795".to_string()));
+    let hostname = spawn_app(7794, server).await;
+
+    let mut alice = ChatClient::connect(&hostname, "alice").await;
+    assert_eq!(alice.recv().await.unwrap(), "* The room contains: ");
+
+    let (mut ws, _) = tokio_tungstenite::connect_async("ws://127.0.0.1:7795")
+        .await
+        .unwrap();
+    assert_eq!(ws_recv(&mut ws).await, "Welcome! What is your name?");
+    ws.send(Message::Text("bob".to_string())).await.unwrap();
+    assert_eq!(ws_recv(&mut ws).await, "* The room contains: alice");
+    assert_eq!(alice.recv().await.unwrap(), "* bob has entered the room");
+
+    ws.send(Message::Text("hi from the browser".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(alice.recv().await.unwrap(), "[bob] hi from the browser");
+
+    alice.send("hi back").await;
+    assert_eq!(ws_recv(&mut ws).await, "[alice] hi back");
+
+    /* one frame stays one line */
+    ws.send(Message::Text("two\nlines".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(alice.recv().await.unwrap(), "[bob] two lines");
+
+    ws.close(None).await.unwrap();
+    assert_eq!(alice.recv().await.unwrap(), "* bob has left the room");
+}
+
+#[tokio::test]
+async fn test_moderation() {
+    let moderation = Moderation::new()
+        .with_banned_words(["darn"])
+        .with_admins(["alice"])
+        .with_admin_token(Some("s3cret".to_string()));
+    let server = ChatServer::new()
+        .with_commands(true)
+        .with_moderation(moderation);
+    let hostname = spawn_app(7796, server).await;
+
+    /* admin names are not first come first served */
+    let mut impostor = ChatClient::connect(&hostname, "alice").await;
+    assert_eq!(impostor.recv().await.unwrap(), "* Name alice is reserved");
+    assert_eq!(impostor.recv().await, None);
+    let mut impostor = ChatClient::connect(&hostname, "alice guess").await;
+    assert_eq!(impostor.recv().await.unwrap(), "* Invalid admin token");
+    assert_eq!(impostor.recv().await, None);
+
+    let mut alice = ChatClient::connect(&hostname, "alice s3cret").await;
+    assert_eq!(alice.recv().await.unwrap(), "* The room contains: ");
+    let mut bob = ChatClient::connect(&hostname, "bob").await;
+    assert_eq!(bob.recv().await.unwrap(), "* The room contains: alice");
+    assert_eq!(alice.recv().await.unwrap(), "* bob has entered the room");
+
+    bob.send("/nick alice").await;
+    assert_eq!(bob.recv().await.unwrap(), "* Name alice is already taken");
+
+    bob.send("Darn it").await;
+    assert_eq!(alice.recv().await.unwrap(), "[bob] **** it");
+
+    /* only admins moderate */
+    bob.send("/mute alice").await;
+    assert_eq!(bob.recv().await.unwrap(), "* Only admins may do that");
+
+    /* a mute longer than a day is refused, not an overflowed clock */
+    alice.send("/mute bob 18446744073709551615").await;
+    assert_eq!(
+        alice.recv().await.unwrap(),
+        "* Usage: /mute <user> [seconds]"
+    );
+
+    alice.send("/mute bob 30").await;
+    assert_eq!(alice.recv().await.unwrap(), "* Muted bob for 30 seconds");
+    assert_eq!(
+        bob.recv().await.unwrap(),
+        "* You were muted by alice for 30 seconds"
+    );
+    bob.send("hello?").await;
+    assert_eq!(
+        bob.recv().await.unwrap(),
+        "* You are muted for another 30 seconds"
+    );
+
+    alice.send("/unmute bob").await;
+    assert_eq!(alice.recv().await.unwrap(), "* Unmuted bob");
+    assert_eq!(bob.recv().await.unwrap(), "* You were unmuted by alice");
+    bob.send("hello!").await;
+    assert_eq!(alice.recv().await.unwrap(), "[bob] hello!");
+
+    let mut carol = ChatClient::connect(&hostname, "carol").await;
+    assert_eq!(
+        carol.recv().await.unwrap(),
+        "* The room contains: alice, bob"
+    );
+    assert_eq!(alice.recv().await.unwrap(), "* carol has entered the room");
+    assert_eq!(bob.recv().await.unwrap(), "* carol has entered the room");
+
+    alice.send("/kick bob").await;
+    assert_eq!(alice.recv().await.unwrap(), "* Kicked bob");
+    assert_eq!(bob.recv().await.unwrap(), "* You were kicked by alice");
+    assert_eq!(bob.recv().await, None);
+    assert_eq!(carol.recv().await.unwrap(), "* bob was kicked");
+
+    alice.send("/kick bob").await;
+    assert_eq!(alice.recv().await.unwrap(), "* No user named bob");
+}
+
+#[tokio::test]
+async fn test_flood_muted() {
+    let moderation = Moderation::new().with_flood_limit(Some(FloodLimit {
+        messages: 3,
+        window: Duration::from_secs(60),
+        mute: Duration::from_secs(60),
+    }));
+    let hostname = spawn_app(7797, ChatServer::new().with_moderation(moderation)).await;
+
+    let mut alice = ChatClient::connect(&hostname, "alice").await;
+    assert_eq!(alice.recv().await.unwrap(), "* The room contains: ");
+    let mut bob = ChatClient::connect(&hostname, "bob").await;
+    assert_eq!(bob.recv().await.unwrap(), "* The room contains: alice");
+    assert_eq!(alice.recv().await.unwrap(), "* bob has entered the room");
+
+    for n in 0..5 {
+        bob.send(&format!("spam {n}")).await;
+    }
+    for n in 0..3 {
+        assert_eq!(alice.recv().await.unwrap(), format!("[bob] spam {n}"));
+    }
+    assert_eq!(
+        bob.recv().await.unwrap(),
+        "* You are muted for 60 seconds for flooding"
+    );
+    assert_eq!(
+        bob.recv().await.unwrap(),
+        "* You are muted for another 60 seconds"
+    );
+
+    /* the others are not affected */
+    alice.send("quiet now").await;
+    assert_eq!(bob.recv().await.unwrap(), "[alice] quiet now");
+}
+
+#[tokio::test]
+async fn test_transcript() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("chat.jsonl");
+    let server = ChatServer::new()
+        .with_commands(true)
+        .with_transcript(Some(path.clone()))
+        .with_transcript_rotation(Rotation::default());
+    let hostname = spawn_app(7798, server).await;
+
+    let mut alice = ChatClient::connect(&hostname, "alice").await;
+    assert_eq!(alice.recv().await.unwrap(), "* The room contains: ");
+    let mut bob = ChatClient::connect(&hostname, "bob").await;
+    assert_eq!(bob.recv().await.unwrap(), "* The room contains: alice");
+    assert_eq!(alice.recv().await.unwrap(), "* bob has entered the room");
+
+    bob.send("hi alice").await;
+    assert_eq!(alice.recv().await.unwrap(), "[bob] hi alice");
+    bob.send("/msg alice psst").await;
+    assert_eq!(alice.recv().await.unwrap(), "[bob -> alice] psst");
+    drop(bob);
+    assert_eq!(alice.recv().await.unwrap(), "* bob has left the room");
+
+    /* written out in the background, give it a moment */
+    let mut records = Vec::new();
+    for _ in 0..50 {
+        let data = std::fs::read_to_string(&path).unwrap();
+        records = data
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        if records.len() == 5 {
+            break;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+
+    let events: Vec<_> = records
+        .iter()
+        .map(|record| {
+            assert!(record["time"].is_string());
+            (
+                record["event"].as_str().unwrap(),
+                record["user"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        events,
+        vec![
+            ("join", "alice"),
+            ("join", "bob"),
+            ("message", "bob"),
+            ("private", "bob"),
+            ("leave", "bob")
+        ]
+    );
+    assert_eq!(records[2]["room"], "lobby");
+    assert_eq!(records[2]["text"], "hi alice");
+    assert_eq!(records[3]["to"], "alice");
+}
+
+#[tokio::test]
+async fn test_websocket_oversized_frame() {
+    let server = ChatServer::new().with_websocket(Some("127.0.0.1:7800".to_string()));
+    let hostname = spawn_app(7799, server).await;
+
+    let mut alice = ChatClient::connect(&hostname, "alice").await;
+    assert_eq!(alice.recv().await.unwrap(), "* The room contains: ");
+
+    let (mut ws, _) = tokio_tungstenite::connect_async("ws://127.0.0.1:7800")
+        .await
+        .unwrap();
+    assert_eq!(ws_recv(&mut ws).await, "Welcome! What is your name?");
+    ws.send(Message::Text("bob".to_string())).await.unwrap();
+    assert_eq!(ws_recv(&mut ws).await, "* The room contains: alice");
+    assert_eq!(alice.recv().await.unwrap(), "* bob has entered the room");
+
+    /* refused from the header, the server does not wait for the payload */
+    let _ = ws.send(Message::Text("x".repeat(1024 * 1024))).await;
+    assert_eq!(alice.recv().await.unwrap(), "* bob has left the room");
+    loop {
+        match timeout(Duration::from_secs(5), ws.next()).await.unwrap() {
+            Some(Ok(Message::Text(text))) => panic!("Unexpected line: {text}"),
+            Some(Ok(_)) => continue,
+            Some(Err(_)) | None => break,
+        }
+    }
+}
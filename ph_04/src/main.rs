@@ -1,6 +1,10 @@
+#![allow(clippy::needless_as_bytes)]
+
 use clap::Parser;
+use ph_common::limits::RateLimiter;
 use std::collections::HashMap;
 use std::error::Error;
+use std::net::IpAddr;
 use std::str;
 use tokio::net::UdpSocket;
 
@@ -14,6 +18,10 @@ struct Args {
     /// IP to listen on
     #[arg(long, default_value_t = String::from("0.0.0.0"))]
     host: String,
+
+    /// Maximum datagrams per second accepted from a single source IP; excess is dropped
+    #[arg(long)]
+    max_messages_per_sec: Option<u32>,
 }
 
 #[tokio::main]
@@ -26,12 +34,24 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let sock = UdpSocket::bind(hostname).await?;
 
     let mut map: HashMap<String, String> = HashMap::new();
+    let mut rate_limiters: HashMap<IpAddr, RateLimiter> = HashMap::new();
 
     let mut buf = [0; 1024];
     loop {
         let (len, addr) = sock.recv_from(&mut buf).await?;
         println!("{len:?} bytes received from {addr:?}");
 
+        if rate_limiters.len() > 1024 {
+            rate_limiters.retain(|_, limiter| !limiter.is_full());
+        }
+        let limiter = rate_limiters
+            .entry(addr.ip())
+            .or_insert_with(|| RateLimiter::new(args.max_messages_per_sec));
+        if !limiter.try_acquire() {
+            println!("Dropping datagram from {addr:?}, rate limited");
+            continue;
+        }
+
         let inp = str::from_utf8(&buf[0..len])?;
         println!("Input: {inp}");
 
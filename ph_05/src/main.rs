@@ -1,10 +1,13 @@
 use clap::Parser;
 use futures::sink::SinkExt;
+use ph_common::limits::{LimitArgs, Limits};
+use ph_common::tls::{Acceptor, Stream, TlsArgs};
 use std::error::Error;
 use std::str::Split;
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpListener;
 use tokio::net::TcpStream;
+use tokio::time::{sleep_until, Instant};
 use tokio_stream::StreamExt;
 use tokio_util::codec::Framed;
 use tokio_util::codec::LinesCodec;
@@ -22,6 +25,12 @@ struct Args {
     /// IP to listen on
     #[arg(long, default_value_t = String::from("0.0.0.0"))]
     host: String,
+
+    #[command(flatten)]
+    tls: TlsArgs,
+
+    #[command(flatten)]
+    limits: LimitArgs,
 }
 
 #[tokio::main]
@@ -31,12 +40,35 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let hostname = format!("{}:{}", args.host, args.port);
     println!("Will start listening on {hostname}");
 
+    let acceptor = Acceptor::from_args(&args.tls)?;
+    let limits = Limits::new(args.limits);
     let listener = TcpListener::bind(hostname).await?;
     loop {
-        let (stream, _addr) = listener.accept().await?;
+        let (stream, addr) = listener.accept().await?;
+        let guard = match limits.acquire(addr.ip()) {
+            Some(guard) => guard,
+            None => {
+                println!(
+                    "Rejected connection from {addr}, {} rejected so far",
+                    limits.rejected()
+                );
+                continue;
+            }
+        };
+        let limits = limits.clone();
+        let acceptor = acceptor.clone();
 
         tokio::spawn(async move {
-            if let Err(e) = handle_client(stream).await {
+            let _guard = guard;
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    println!("TLS handshake failed: {e:?}");
+                    return;
+                }
+            };
+
+            if let Err(e) = handle_client(stream, limits).await {
                 println!("Error occurred: {e:?}");
             }
         });
@@ -72,10 +104,13 @@ fn rewrite_line(line: &str) -> String {
     out_line
 }
 
-async fn handle_client(stream: TcpStream) -> Result<(), Box<dyn Error>> {
+async fn handle_client(stream: Stream, limits: Limits) -> Result<(), Box<dyn Error>> {
     println!("New connection: {}", stream.peer_addr().unwrap());
 
-    let mut codec = Framed::new(stream, LinesCodec::new_with_max_length(2000));
+    let mut codec = Framed::new(limits.wrap(stream), LinesCodec::new_with_max_length(2000));
+    let mut rate_limiter = limits.rate_limiter();
+    /* over the rate, reading pauses while upstream lines still go out */
+    let mut resume_at = None;
 
     let upstream_stream = TcpStream::connect(UPSTREAM_HOST).await?;
     let mut upstream_codec = Framed::new(upstream_stream, LinesCodec::new_with_max_length(2000));
@@ -103,16 +138,20 @@ async fn handle_client(stream: TcpStream) -> Result<(), Box<dyn Error>> {
                 },
                 None => break,
             },
-            result = codec.next() => match result {
+            _ = sleep_until(resume_at.unwrap_or_else(Instant::now)), if resume_at.is_some() => {
+                resume_at = None;
+            },
+            result = codec.next(), if resume_at.is_none() => match result {
                 Some(Ok(msg)) => {
 
-                    let peer = codec.get_ref().peer_addr();
+                    let peer = codec.get_ref().get_ref().peer_addr();
                     if let Err(_err) = peer {
                         println!("no msg");
                         break
                     };
 
                     println!("Received: {msg}");
+                    resume_at = rate_limiter.resume_at();
 
                     let out_msg = rewrite_line(&msg);
 
@@ -129,7 +168,7 @@ async fn handle_client(stream: TcpStream) -> Result<(), Box<dyn Error>> {
         }
     }
 
-    if let Ok(_ok) = codec.get_ref().peer_addr() {
+    if let Ok(_ok) = codec.get_ref().get_ref().peer_addr() {
         codec.get_mut().shutdown().await?;
     }
     if let Ok(_ok) = upstream_codec.get_ref().peer_addr() {
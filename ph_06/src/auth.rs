@@ -0,0 +1,104 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+use crate::server::Road;
+
+/*
+ * Per-road allowlist of dispatcher source IPs.
+ *
+ * Config file format, one road per line:
+ *
+ *   # road  allowed IPs
+ *   123     127.0.0.1 10.0.0.5
+ *   4654    ::1
+ *
+ * Roads that are not listed cannot be claimed by any dispatcher.
+ */
+#[derive(Debug, Default)]
+pub struct DispatcherAuth {
+    allowed: HashMap<Road, HashSet<IpAddr>>,
+}
+
+impl DispatcherAuth {
+    pub fn new() -> DispatcherAuth {
+        DispatcherAuth::default()
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<DispatcherAuth> {
+        DispatcherAuth::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(config: &str) -> io::Result<DispatcherAuth> {
+        let mut auth = DispatcherAuth::new();
+
+        for (lineno, line) in config.lines().enumerate() {
+            let line = match line.split_once('#') {
+                Some((line, _comment)) => line,
+                None => line,
+            };
+
+            let mut tokens = line.split_whitespace();
+            let road = match tokens.next() {
+                Some(road) => road,
+                None => continue,
+            };
+            let road: Road = road.parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid road on line {}: {road}", lineno + 1),
+                )
+            })?;
+
+            for ip in tokens {
+                let ip: IpAddr = ip.parse().map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid IP on line {}: {ip}", lineno + 1),
+                    )
+                })?;
+                auth.allow(road, ip);
+            }
+        }
+
+        Ok(auth)
+    }
+
+    pub fn allow(&mut self, road: Road, ip: IpAddr) {
+        self.allowed.entry(road).or_default().insert(ip);
+    }
+
+    pub fn is_permitted(&self, road: Road, ip: IpAddr) -> bool {
+        self.allowed.get(&road).is_some_and(|ips| ips.contains(&ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = "# road  allowed IPs\n\
+                      123 127.0.0.1 10.0.0.5\n\
+                      \n\
+                      4654 ::1 # loopback only\n";
+
+        let auth = DispatcherAuth::parse(config).unwrap();
+
+        assert!(auth.is_permitted(123, "127.0.0.1".parse().unwrap()));
+        assert!(auth.is_permitted(123, "10.0.0.5".parse().unwrap()));
+        assert!(auth.is_permitted(4654, "::1".parse().unwrap()));
+        assert!(!auth.is_permitted(4654, "127.0.0.1".parse().unwrap()));
+        assert!(!auth.is_permitted(66, "127.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_parse_config_invalid() {
+        assert!(DispatcherAuth::parse("road 127.0.0.1").is_err());
+        assert!(DispatcherAuth::parse("123 localhost").is_err());
+        assert!(DispatcherAuth::parse("70000 127.0.0.1").is_err());
+    }
+}
@@ -320,6 +320,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_msg_err_decode() {
+        /* only the server sends these, a client doing so is in error */
+        let data = b"\x10\x0b\x69\x6c\x6c\x65\x67\x61\x6c\x20\x6d\x73\x67";
+
+        let mut codec = SpeedDaemonCodec::new();
+        let mut input_buf = BytesMut::with_capacity(128);
+        input_buf.put_slice(&data[..]);
+
+        if let Ok(output) = codec.decode(&mut input_buf) {
+            panic!("Decoded an Error message: {:?}", output);
+        }
+    }
+
     #[test]
     fn test_msg_plate() {
         let data = b"\x20\x04\x55\x4e\x31\x58\x00\x00\x03\xe8";
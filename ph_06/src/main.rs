@@ -8,7 +8,10 @@ pub mod server;
 pub mod test;
 */
 
+use ph_06::auth::DispatcherAuth;
 use ph_06::server;
+use ph_common::limits::{LimitArgs, Limits};
+use ph_common::tls::{Acceptor, TlsArgs};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -20,6 +23,16 @@ struct Args {
     /// IP to listen on
     #[arg(long, default_value_t = String::from("0.0.0.0"))]
     host: String,
+
+    /// Per-road dispatcher IP allowlist; when unset any dispatcher may claim any road
+    #[arg(long)]
+    dispatcher_auth: Option<String>,
+
+    #[command(flatten)]
+    tls: TlsArgs,
+
+    #[command(flatten)]
+    limits: LimitArgs,
 }
 
 #[tokio::main]
@@ -29,7 +42,13 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let hostname = format!("{}:{}", args.host, args.port);
     println!("Will start listening on {hostname}");
 
-    let server = server::SpeedDaemonServer::new();
+    let mut server = server::SpeedDaemonServer::new()
+        .with_tls(Acceptor::from_args(&args.tls)?)
+        .with_limits(Limits::new(args.limits));
+    if let Some(path) = args.dispatcher_auth {
+        server = server.with_dispatcher_auth(DispatcherAuth::from_file(path)?);
+    }
+
     server.run(hostname).await?;
 
     Ok(())
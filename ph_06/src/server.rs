@@ -1,18 +1,22 @@
 use futures::sink::SinkExt;
+use ph_common::limits::{IdleTimeout, Limits};
+use ph_common::tls::{Acceptor, Stream};
 use std::cmp::Ordering;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::error::Error;
-use std::hash::Hash;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use tokio::net::TcpListener;
-use tokio::net::TcpStream;
 use tokio::sync::mpsc;
 use tokio::sync::Mutex;
+use tokio::time::{sleep_until, Instant};
 use tokio_stream::StreamExt;
 use tokio_util::codec::Framed;
 
+use crate::auth::DispatcherAuth;
 use crate::codec::SDMessage;
 use crate::codec::SpeedDaemonCodec;
 use crate::codec::SpeedDaemonCodecError;
@@ -20,20 +24,15 @@ use crate::heartbeat;
 
 pub type MsgTx = mpsc::UnboundedSender<SDMessage>;
 type MsgRx = mpsc::UnboundedReceiver<SDMessage>;
+type ClientCodec = Framed<IdleTimeout<Stream>, SpeedDaemonCodec>;
 
-type Road = u16;
+pub type Road = u16;
 type Limit = u16;
 type Mile = u16;
 type Timestamp = u32;
 type Plate = String;
 type Day = u32;
 
-#[derive(Debug, Eq, PartialEq, Hash)]
-struct PlateReportKey {
-    road: Road,
-    plate: Plate,
-}
-
 #[derive(Debug)]
 struct PlateReport {
     timestamp: Timestamp,
@@ -46,6 +45,7 @@ pub struct AppState {
     pending_tickets: HashMap<Road, Vec<SDMessage>>,
     cars_on_road: HashMap<(Road, Plate), Vec<PlateReport>>,
     tickets_per_day: HashSet<(Plate, Day)>,
+    dispatcher_auth: Option<DispatcherAuth>,
 }
 
 fn add_pending_ticket(
@@ -57,12 +57,20 @@ fn add_pending_ticket(
 }
 
 impl AppState {
-    fn new() -> AppState {
+    fn new(dispatcher_auth: Option<DispatcherAuth>) -> AppState {
         AppState {
             dispatchers_on_road: HashMap::new(),
             pending_tickets: HashMap::new(),
             cars_on_road: HashMap::new(),
             tickets_per_day: HashSet::new(),
+            dispatcher_auth,
+        }
+    }
+
+    fn is_dispatcher_permitted(&self, road: Road, ip: IpAddr) -> bool {
+        match &self.dispatcher_auth {
+            Some(auth) => auth.is_permitted(road, ip),
+            None => true,
         }
     }
 
@@ -71,7 +79,7 @@ impl AppState {
             for road in &dispatcher.roads {
                 self.dispatchers_on_road
                     .entry(*road)
-                    .or_insert_with(Vec::new)
+                    .or_default()
                     .push(client.tx.clone());
 
                 /* send pending ticket if there are any for this road */
@@ -200,6 +208,7 @@ enum ClientType {
 
 #[derive(Debug)]
 struct Client {
+    addr: SocketAddr,
     typ: ClientType,
     camera: Option<Camera>,
     ticket_dispatcher: Option<TicketDispatcher>,
@@ -209,10 +218,11 @@ struct Client {
 }
 
 impl Client {
-    fn new() -> Client {
+    fn new(addr: SocketAddr) -> Client {
         let (tx, rx) = mpsc::unbounded_channel();
 
         Client {
+            addr,
             typ: ClientType::Unknown,
             camera: None,
             ticket_dispatcher: None,
@@ -224,7 +234,7 @@ impl Client {
 
     async fn send_error(
         self: &mut Client,
-        codec: &mut Framed<TcpStream, SpeedDaemonCodec>,
+        codec: &mut ClientCodec,
         msg: String,
     ) -> Result<(), Box<dyn Error>> {
         codec.send(SDMessage::Error { msg }).await?;
@@ -232,13 +242,22 @@ impl Client {
         Ok(())
     }
 
+    /*
+     * Cameras and dispatchers may well go quiet once identified, dispatchers
+     * while tickets are still owed to them, so the idle timeout only guards
+     * against connections that never say what they are.
+     */
+    fn stop_idle_timeout(codec: &mut ClientCodec) {
+        codec.get_mut().set_timeout(None);
+    }
+
     /* TODO: convert result error to something else. anyhow? thiserror? */
 
     async fn process_msg(
         self: &mut Client,
         msg: Option<Result<SDMessage, SpeedDaemonCodecError>>,
         state: &Arc<Mutex<AppState>>,
-        codec: &mut Framed<TcpStream, SpeedDaemonCodec>,
+        codec: &mut ClientCodec,
     ) -> Result<(), Box<dyn Error>> {
         match msg {
             Some(Ok(SDMessage::IAmCamera { road, mile, limit })) => {
@@ -250,6 +269,7 @@ impl Client {
 
                 self.typ = ClientType::Camera;
                 self.camera = Some(Camera { road, mile, limit });
+                Client::stop_idle_timeout(codec);
             }
             Some(Ok(SDMessage::IAmDispatcher { roads })) => {
                 if self.typ != ClientType::Unknown {
@@ -258,10 +278,23 @@ impl Client {
                     return Ok(());
                 }
 
+                let mut state = state.lock().await;
+                let denied = roads
+                    .iter()
+                    .find(|road| !state.is_dispatcher_permitted(**road, self.addr.ip()));
+
+                /* like any other protocol error, the client is disconnected */
+                if let Some(road) = denied {
+                    drop(state);
+                    let msg = format!("Not authorized for road {road}");
+                    self.send_error(codec, msg.clone()).await?;
+                    return Err(format!("Dispatcher {}: {msg}", self.addr).into());
+                }
+
                 self.typ = ClientType::TicketDispatcher;
                 self.ticket_dispatcher = Some(TicketDispatcher { roads });
-
-                state.lock().await.add_dispatcher(self);
+                state.add_dispatcher(self);
+                Client::stop_idle_timeout(codec);
             }
             Some(Ok(SDMessage::Plate { plate, timestamp })) => {
                 if self.typ != ClientType::Camera {
@@ -280,6 +313,7 @@ impl Client {
                 }
                 if interval > 0 {
                     self.is_heartbeat_running = true;
+                    Client::stop_idle_timeout(codec);
                     let tx = self.tx.clone();
 
                     /* TODO: how to cleanup when client disconnects? */
@@ -300,23 +334,66 @@ impl Client {
 }
 
 #[derive(Debug, Default)]
-pub struct SpeedDaemonServer {}
+pub struct SpeedDaemonServer {
+    dispatcher_auth: Option<DispatcherAuth>,
+    acceptor: Acceptor,
+    limits: Limits,
+}
 
 impl SpeedDaemonServer {
     pub fn new() -> SpeedDaemonServer {
         SpeedDaemonServer::default()
     }
 
+    /* only allow dispatchers to claim roads they are permitted for */
+    pub fn with_dispatcher_auth(mut self, auth: DispatcherAuth) -> SpeedDaemonServer {
+        self.dispatcher_auth = Some(auth);
+        self
+    }
+
+    /* terminate TLS on incoming connections */
+    pub fn with_tls(mut self, acceptor: Acceptor) -> SpeedDaemonServer {
+        self.acceptor = acceptor;
+        self
+    }
+
+    /* cap connections, throttle chatty clients and drop idle ones */
+    pub fn with_limits(mut self, limits: Limits) -> SpeedDaemonServer {
+        self.limits = limits;
+        self
+    }
+
     pub async fn run(self, hostname: String) -> Result<(), Box<dyn Error>> {
         let listener = TcpListener::bind(hostname).await?;
-        let state = Arc::new(Mutex::new(AppState::new()));
+        let state = Arc::new(Mutex::new(AppState::new(self.dispatcher_auth)));
 
         loop {
-            let (stream, _addr) = listener.accept().await?;
+            let (stream, addr) = listener.accept().await?;
+            let guard = match self.limits.acquire(addr.ip()) {
+                Some(guard) => guard,
+                None => {
+                    println!(
+                        "Rejected connection from {addr}, {} rejected so far",
+                        self.limits.rejected()
+                    );
+                    continue;
+                }
+            };
             let state = state.clone();
+            let acceptor = self.acceptor.clone();
+            let limits = self.limits.clone();
 
             tokio::spawn(async move {
-                if let Err(e) = SpeedDaemonServer::handle_client(stream, state).await {
+                let _guard = guard;
+                let stream = match acceptor.accept(stream).await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        println!("TLS handshake failed: {e:?}");
+                        return;
+                    }
+                };
+
+                if let Err(e) = SpeedDaemonServer::handle_client(stream, state, limits).await {
                     println!("Error occurred: {e:?}");
                 }
             });
@@ -324,23 +401,35 @@ impl SpeedDaemonServer {
     }
 
     pub async fn handle_client(
-        stream: TcpStream,
+        stream: Stream,
         state: Arc<Mutex<AppState>>,
+        limits: Limits,
     ) -> Result<(), Box<dyn Error>> {
-        println!("New connection: {}", stream.peer_addr().unwrap());
+        let addr = stream.peer_addr()?;
+        println!("New connection: {addr}");
 
-        let mut codec = Framed::new(stream, SpeedDaemonCodec::new());
-        let mut client = Client::new();
+        let mut codec = Framed::new(limits.wrap(stream), SpeedDaemonCodec::new());
+        let mut client = Client::new(addr);
+        let mut rate_limiter = limits.rate_limiter();
+        /* over the rate, reading pauses while tickets and heartbeats still go out */
+        let mut resume_at = None;
 
         loop {
             tokio::select! {
-                msg = codec.next() => match msg {
+                msg = codec.next(), if resume_at.is_none() => match msg {
                     Some(Ok(msg)) => {
                         println!("Received message: {msg:?}");
 
+                        resume_at = rate_limiter.resume_at();
                         client.process_msg(Some(Ok(msg)), &state, &mut codec).await?;
                     },
+                    Some(Err(SpeedDaemonCodecError::IoError(e))) if e.kind() == io::ErrorKind::TimedOut => {
+                        println!("Client idle for too long");
+                        let _ = client.send_error(&mut codec, "Idle timeout".to_string()).await;
+                        break;
+                    }
                     Some(Err(e)) => {
+                        println!("Error decoding message: {e:?}");
                         let _ = client.send_error(&mut codec, "Unknown message type".to_string()).await;
                         break;
                     }
@@ -348,6 +437,9 @@ impl SpeedDaemonServer {
                         break;
                     }
                 },
+                _ = sleep_until(resume_at.unwrap_or_else(Instant::now)), if resume_at.is_some() => {
+                    resume_at = None;
+                },
                 msg = client.rx.recv() => match msg {
                     Some(msg) => {
                         println!("Sending message: {msg:?}");
@@ -1,12 +1,22 @@
+#![allow(clippy::bool_assert_comparison)]
+
+use bytes::{Buf, BufMut, BytesMut};
 use futures::SinkExt;
+use std::sync::Arc;
 use tokio::net::TcpStream;
-use tokio::time::{sleep, Duration};
+use tokio::time::{sleep, timeout, Duration};
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
 use tokio_stream::StreamExt;
-use tokio_util::codec::Framed;
+use tokio_util::codec::{Decoder, Encoder, Framed};
 
+use ph_06::auth::DispatcherAuth;
 use ph_06::codec::SpeedDaemonCodec;
 use ph_06::codec::*;
 use ph_06::server::SpeedDaemonServer;
+use ph_common::limits::{LimitArgs, Limits};
+use ph_common::tls::Acceptor;
 
 async fn spawn_app() {
     let server = SpeedDaemonServer::new();
@@ -16,6 +26,55 @@ async fn spawn_app() {
     sleep(Duration::from_millis(100)).await;
 }
 
+/*
+ * The server codec only reads what clients send and only writes what the
+ * server sends, so tests speaking the other way round go through this.
+ */
+struct ClientCodec(SpeedDaemonCodec);
+
+impl Decoder for ClientCodec {
+    type Item = SDMessage;
+    type Error = SpeedDaemonCodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<SDMessage>, Self::Error> {
+        /* Heartbeat: u8 0x41 */
+        if src.first() == Some(&0x41) {
+            src.advance(1);
+            return Ok(Some(SDMessage::Heartbeat));
+        }
+        /* Error: u8 0x10, str msg */
+        if src.first() != Some(&0x10) {
+            return self.0.decode(src);
+        }
+        let Some(&msg_len) = src.get(1) else {
+            return Ok(None);
+        };
+        if src.len() < 2 + usize::from(msg_len) {
+            return Ok(None);
+        }
+
+        src.advance(2);
+        let msg = src.split_to(msg_len.into());
+        Ok(Some(SDMessage::Error {
+            msg: String::from_utf8_lossy(&msg).to_string(),
+        }))
+    }
+}
+
+impl Encoder<SDMessage> for ClientCodec {
+    type Error = SpeedDaemonCodecError;
+
+    fn encode(&mut self, item: SDMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        /* WantHeartbeat: u8 0x40, u32 interval */
+        if let SDMessage::WantHeartbeat { interval } = item {
+            dst.put_u8(0x40);
+            dst.put_u32(interval);
+            return Ok(());
+        }
+        self.0.encode(item, dst)
+    }
+}
+
 #[tokio::test]
 async fn test_example_session() {
     spawn_app().await;
@@ -171,10 +230,298 @@ async fn test_same_day_ticket() {
         }
     }
 
-    sleep(Duration::from_millis(100)).await;
-    let msg = codec_dispatcher.next().await;
+    let msg = timeout(Duration::from_millis(500), codec_dispatcher.next()).await;
     dbg!(&msg);
-    if msg.is_some() {
+    if let Ok(Some(_)) = msg {
         panic!("Should not receive 2 tickets");
     }
 }
+
+#[tokio::test]
+async fn test_dispatcher_auth() {
+    let hostname = "127.0.0.1:7779".to_string();
+    let auth = DispatcherAuth::parse("123 127.0.0.1\n456 10.0.0.1\n").unwrap();
+    let server = SpeedDaemonServer::new().with_dispatcher_auth(auth);
+    let listen = hostname.clone();
+    tokio::spawn(async move {
+        let _ = server.run(listen).await;
+    });
+    sleep(Duration::from_millis(100)).await;
+
+    let stream = TcpStream::connect(&hostname).await.unwrap();
+    let mut codec_camera1 = Framed::new(stream, SpeedDaemonCodec::new());
+    let stream = TcpStream::connect(&hostname).await.unwrap();
+    let mut codec_camera2 = Framed::new(stream, SpeedDaemonCodec::new());
+    let stream = TcpStream::connect(&hostname).await.unwrap();
+    let mut codec_denied = Framed::new(stream, ClientCodec(SpeedDaemonCodec::new()));
+    let stream = TcpStream::connect(&hostname).await.unwrap();
+    let mut codec_dispatcher = Framed::new(stream, SpeedDaemonCodec::new());
+
+    /* road 456 is not permitted from localhost, so the whole claim is refused */
+    let iamdispatcher = SDMessage::IAmDispatcher {
+        roads: vec![123, 456],
+    };
+    assert!(codec_denied.send(iamdispatcher).await.is_ok());
+
+    let msg = codec_denied.next().await;
+    match msg {
+        Some(Ok(SDMessage::Error { msg })) => {
+            assert_eq!(msg, "Not authorized for road 456");
+        }
+        _ => {
+            panic!("Did not receive correct message: {msg:?}");
+        }
+    }
+    let msg = timeout(Duration::from_millis(500), codec_denied.next()).await;
+    assert!(matches!(msg, Ok(None)), "Not disconnected: {msg:?}");
+
+    /* a dispatcher sticking to its permitted road gets the tickets */
+    let iamdispatcher = SDMessage::IAmDispatcher { roads: vec![123] };
+    assert!(codec_dispatcher.send(iamdispatcher).await.is_ok());
+    sleep(Duration::from_millis(100)).await;
+
+    let iamcamera = SDMessage::IAmCamera {
+        road: 123,
+        mile: 8,
+        limit: 60,
+    };
+    assert!(codec_camera1.send(iamcamera).await.is_ok());
+    let plate = SDMessage::Plate {
+        plate: "UN1X".to_string(),
+        timestamp: 0,
+    };
+    assert!(codec_camera1.send(plate).await.is_ok());
+
+    let iamcamera = SDMessage::IAmCamera {
+        road: 123,
+        mile: 9,
+        limit: 60,
+    };
+    assert!(codec_camera2.send(iamcamera).await.is_ok());
+    let plate = SDMessage::Plate {
+        plate: "UN1X".to_string(),
+        timestamp: 45,
+    };
+    assert!(codec_camera2.send(plate).await.is_ok());
+
+    let msg = codec_dispatcher.next().await;
+    match msg {
+        Some(Ok(SDMessage::Ticket { plate, road, .. })) => {
+            assert_eq!(plate, "UN1X".to_string());
+            assert_eq!(road, 123);
+        }
+        _ => {
+            panic!("Did not receive correct message: {msg:?}");
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_tls_session() {
+    let dir = std::env::temp_dir().join(format!("ph_06-tls-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    std::fs::write(dir.join("cert.pem"), certified.cert.pem()).unwrap();
+    std::fs::write(dir.join("key.pem"), certified.key_pair.serialize_pem()).unwrap();
+
+    let hostname = "127.0.0.1:7780".to_string();
+    let acceptor = Acceptor::tls(dir.join("cert.pem"), dir.join("key.pem")).unwrap();
+    let server = SpeedDaemonServer::new().with_tls(acceptor);
+    let listen = hostname.clone();
+    tokio::spawn(async move {
+        let _ = server.run(listen).await;
+    });
+    sleep(Duration::from_millis(100)).await;
+
+    let mut roots = RootCertStore::empty();
+    roots.add(certified.cert.der().clone()).unwrap();
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let connector = TlsConnector::from(Arc::new(config));
+
+    let mut codecs = Vec::new();
+    for _ in 0..3 {
+        let stream = TcpStream::connect(&hostname).await.unwrap();
+        let domain = ServerName::try_from("localhost").unwrap();
+        let stream = connector.connect(domain, stream).await.unwrap();
+        codecs.push(Framed::new(stream, SpeedDaemonCodec::new()));
+    }
+    let mut codec_dispatcher = codecs.pop().unwrap();
+    let mut codec_camera2 = codecs.pop().unwrap();
+    let mut codec_camera1 = codecs.pop().unwrap();
+
+    let iamdispatcher = SDMessage::IAmDispatcher { roads: vec![123] };
+    assert!(codec_dispatcher.send(iamdispatcher).await.is_ok());
+    sleep(Duration::from_millis(100)).await;
+
+    let iamcamera = SDMessage::IAmCamera {
+        road: 123,
+        mile: 8,
+        limit: 60,
+    };
+    assert!(codec_camera1.send(iamcamera).await.is_ok());
+    let plate = SDMessage::Plate {
+        plate: "UN1X".to_string(),
+        timestamp: 0,
+    };
+    assert!(codec_camera1.send(plate).await.is_ok());
+
+    let iamcamera = SDMessage::IAmCamera {
+        road: 123,
+        mile: 9,
+        limit: 60,
+    };
+    assert!(codec_camera2.send(iamcamera).await.is_ok());
+    let plate = SDMessage::Plate {
+        plate: "UN1X".to_string(),
+        timestamp: 45,
+    };
+    assert!(codec_camera2.send(plate).await.is_ok());
+
+    let msg = codec_dispatcher.next().await;
+    match msg {
+        Some(Ok(SDMessage::Ticket {
+            plate, road, speed, ..
+        })) => {
+            assert_eq!(plate, "UN1X".to_string());
+            assert_eq!(road, 123);
+            assert_eq!(speed, 8000);
+        }
+        _ => {
+            panic!("Did not receive correct message: {msg:?}");
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_connection_limit() {
+    let hostname = "127.0.0.1:7781".to_string();
+    let limits = Limits::new(LimitArgs {
+        max_connections_per_ip: Some(1),
+        idle_timeout: Some(1),
+        ..Default::default()
+    });
+    let server = SpeedDaemonServer::new().with_limits(limits.clone());
+    let listen = hostname.clone();
+    tokio::spawn(async move {
+        let _ = server.run(listen).await;
+    });
+    sleep(Duration::from_millis(100)).await;
+
+    let stream = TcpStream::connect(&hostname).await.unwrap();
+    let mut codec_first = Framed::new(stream, ClientCodec(SpeedDaemonCodec::new()));
+    sleep(Duration::from_millis(100)).await;
+
+    /* second connection from the same IP is closed right away */
+    let stream = TcpStream::connect(&hostname).await.unwrap();
+    let mut codec_second = Framed::new(stream, SpeedDaemonCodec::new());
+    let msg = timeout(Duration::from_millis(500), codec_second.next()).await;
+    assert!(matches!(msg, Ok(None) | Ok(Some(Err(_)))));
+    assert_eq!(limits.rejected(), 1);
+
+    /* first one is dropped after being idle */
+    let msg = timeout(Duration::from_secs(3), codec_first.next()).await;
+    match msg {
+        Ok(Some(Ok(SDMessage::Error { msg }))) => assert_eq!(msg, "Idle timeout"),
+        _ => panic!("Did not receive correct message: {msg:?}"),
+    }
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(limits.active(), 0);
+}
+
+#[tokio::test]
+async fn test_identified_not_idle() {
+    let hostname = "127.0.0.1:7782".to_string();
+    let limits = Limits::new(LimitArgs {
+        idle_timeout: Some(1),
+        ..Default::default()
+    });
+    let server = SpeedDaemonServer::new().with_limits(limits);
+    let listen = hostname.clone();
+    tokio::spawn(async move {
+        let _ = server.run(listen).await;
+    });
+    sleep(Duration::from_millis(100)).await;
+
+    let stream = TcpStream::connect(&hostname).await.unwrap();
+    let mut codec_dispatcher = Framed::new(stream, SpeedDaemonCodec::new());
+    let iamdispatcher = SDMessage::IAmDispatcher { roads: vec![123] };
+    assert!(codec_dispatcher.send(iamdispatcher).await.is_ok());
+
+    /* quiet for longer than the timeout, still waiting for tickets */
+    sleep(Duration::from_secs(2)).await;
+
+    let mut cameras = Vec::new();
+    for (mile, timestamp) in [(8, 0), (9, 45)] {
+        let stream = TcpStream::connect(&hostname).await.unwrap();
+        let mut codec_camera = Framed::new(stream, SpeedDaemonCodec::new());
+        let iamcamera = SDMessage::IAmCamera {
+            road: 123,
+            mile,
+            limit: 60,
+        };
+        assert!(codec_camera.send(iamcamera).await.is_ok());
+        let plate = SDMessage::Plate {
+            plate: "UN1X".to_string(),
+            timestamp,
+        };
+        assert!(codec_camera.send(plate).await.is_ok());
+        cameras.push(codec_camera);
+    }
+
+    let msg = timeout(Duration::from_secs(1), codec_dispatcher.next()).await;
+    match msg {
+        Ok(Some(Ok(SDMessage::Ticket { plate, road, .. }))) => {
+            assert_eq!(plate, "UN1X".to_string());
+            assert_eq!(road, 123);
+        }
+        _ => {
+            panic!("Did not receive correct message: {msg:?}");
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_throttled_heartbeats() {
+    let hostname = "127.0.0.1:7783".to_string();
+    let limits = Limits::new(LimitArgs {
+        max_messages_per_sec: Some(1),
+        ..Default::default()
+    });
+    let server = SpeedDaemonServer::new().with_limits(limits);
+    let listen = hostname.clone();
+    tokio::spawn(async move {
+        let _ = server.run(listen).await;
+    });
+    sleep(Duration::from_millis(100)).await;
+
+    let stream = TcpStream::connect(&hostname).await.unwrap();
+    let mut codec_camera = Framed::new(stream, ClientCodec(SpeedDaemonCodec::new()));
+
+    /* every 0.1s, while the plates behind it take many seconds to get read */
+    let want = SDMessage::WantHeartbeat { interval: 1 };
+    assert!(codec_camera.send(want).await.is_ok());
+    let iamcamera = SDMessage::IAmCamera {
+        road: 123,
+        mile: 8,
+        limit: 60,
+    };
+    assert!(codec_camera.send(iamcamera).await.is_ok());
+    for timestamp in 0..20 {
+        let plate = SDMessage::Plate {
+            plate: "UN1X".to_string(),
+            timestamp,
+        };
+        assert!(codec_camera.send(plate).await.is_ok());
+    }
+
+    let mut heartbeats = 0;
+    let _ = timeout(Duration::from_secs(1), async {
+        while let Some(Ok(SDMessage::Heartbeat)) = codec_camera.next().await {
+            heartbeats += 1;
+        }
+    })
+    .await;
+    assert!(heartbeats >= 5, "Only {heartbeats} heartbeats");
+}
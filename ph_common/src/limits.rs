@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Sleep;
+
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct LimitArgs {
+    /// Maximum number of simultaneous connections
+    #[arg(long)]
+    pub max_connections: Option<usize>,
+
+    /// Maximum number of simultaneous connections from a single source IP
+    #[arg(long)]
+    pub max_connections_per_ip: Option<usize>,
+
+    /// Maximum messages per second accepted from a single client; excess is throttled
+    #[arg(long)]
+    pub max_messages_per_sec: Option<u32>,
+
+    /// Disconnect clients that send nothing for this many seconds
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub idle_timeout: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct Connections {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+#[derive(Debug, Default)]
+struct LimitsInner {
+    args: LimitArgs,
+    connections: Mutex<Connections>,
+    rejected: AtomicU64,
+}
+
+/*
+ * Connection accounting shared by all clients of one listener.
+ *
+ * Usable from both the tokio servers and the thread-per-client ones, the
+ * lock is only held for the counter updates.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    inner: Arc<LimitsInner>,
+}
+
+impl Limits {
+    pub fn new(args: LimitArgs) -> Limits {
+        Limits {
+            inner: Arc::new(LimitsInner {
+                args,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /* returns None (and counts the rejection) when a cap is reached */
+    pub fn acquire(&self, ip: IpAddr) -> Option<ConnectionGuard> {
+        let args = &self.inner.args;
+        let mut connections = self.inner.connections.lock().unwrap();
+
+        let per_ip = connections.per_ip.get(&ip).copied().unwrap_or(0);
+        if args
+            .max_connections
+            .is_some_and(|max| connections.total >= max)
+            || args.max_connections_per_ip.is_some_and(|max| per_ip >= max)
+        {
+            self.inner.rejected.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        connections.total += 1;
+        *connections.per_ip.entry(ip).or_default() += 1;
+
+        Some(ConnectionGuard {
+            limits: self.clone(),
+            ip,
+        })
+    }
+
+    fn release(&self, ip: IpAddr) {
+        let mut connections = self.inner.connections.lock().unwrap();
+
+        connections.total -= 1;
+        if let Some(count) = connections.per_ip.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                connections.per_ip.remove(&ip);
+            }
+        }
+    }
+
+    pub fn active(&self) -> usize {
+        self.inner.connections.lock().unwrap().total
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.inner.rejected.load(Ordering::Relaxed)
+    }
+
+    pub fn rate_limiter(&self) -> RateLimiter {
+        RateLimiter::new(self.inner.args.max_messages_per_sec)
+    }
+
+    /* for services that must not go unthrottled, per_sec unless configured */
+    pub fn rate_limiter_or(&self, per_sec: u32) -> RateLimiter {
+        RateLimiter::new(self.inner.args.max_messages_per_sec.or(Some(per_sec)))
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.inner.args.idle_timeout.map(Duration::from_secs)
+    }
+
+    pub fn wrap<S>(&self, stream: S) -> IdleTimeout<S> {
+        IdleTimeout::new(stream, self.idle_timeout())
+    }
+}
+
+/* keeps the connection counted until dropped */
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    limits: Limits,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.limits.release(self.ip);
+    }
+}
+
+/* token bucket, allows bursts of up to one second worth of messages */
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: Option<f64>,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_sec: Option<u32>) -> RateLimiter {
+        let rate = per_sec.map(|rate| rate.max(1) as f64);
+
+        RateLimiter {
+            rate,
+            tokens: rate.unwrap_or(0.0),
+            last: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, rate: f64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        self.last = now;
+    }
+
+    /* takes a token, returning how long the caller has to wait for it */
+    fn reserve(&mut self) -> Option<Duration> {
+        let rate = self.rate?;
+
+        self.refill(rate);
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            None
+        } else {
+            Some(Duration::from_secs_f64(-self.tokens / rate))
+        }
+    }
+
+    /* non-blocking variant for connectionless servers, drops instead of waiting */
+    pub fn try_acquire(&mut self) -> bool {
+        let rate = match self.rate {
+            Some(rate) => rate,
+            None => return true,
+        };
+
+        self.refill(rate);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub async fn acquire(&mut self) {
+        if let Some(delay) = self.reserve() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /*
+     * For select! loops: takes a token and, if over the rate, returns when to
+     * read again, so the loop can stop reading until then and keep writing.
+     */
+    pub fn resume_at(&mut self) -> Option<tokio::time::Instant> {
+        self.reserve()
+            .map(|delay| tokio::time::Instant::now() + delay)
+    }
+
+    pub fn acquire_blocking(&mut self) {
+        if let Some(delay) = self.reserve() {
+            std::thread::sleep(delay);
+        }
+    }
+
+    /* a full bucket behaves exactly like a fresh one and can be discarded */
+    pub fn is_full(&mut self) -> bool {
+        match self.rate {
+            Some(rate) => {
+                self.refill(rate);
+                self.tokens >= rate
+            }
+            None => true,
+        }
+    }
+}
+
+/* fails reads with TimedOut when nothing arrives for the configured time */
+#[derive(Debug)]
+pub struct IdleTimeout<S> {
+    inner: S,
+    timeout: Option<Duration>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> IdleTimeout<S> {
+    pub fn new(inner: S, timeout: Option<Duration>) -> IdleTimeout<S> {
+        IdleTimeout {
+            inner,
+            timeout,
+            sleep: None,
+        }
+    }
+
+    /* None stops timing out, e.g. for clients that may legitimately go quiet */
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+        self.sleep = None;
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for IdleTimeout<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Ready(result) => {
+                this.sleep = None;
+                Poll::Ready(result)
+            }
+            Poll::Pending => {
+                let timeout = match this.timeout {
+                    Some(timeout) => timeout,
+                    None => return Poll::Pending,
+                };
+
+                let sleep = this
+                    .sleep
+                    .get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+                match sleep.as_mut().poll(cx) {
+                    Poll::Ready(()) => Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "Client idle for too long",
+                    ))),
+                    Poll::Pending => Poll::Pending,
+                }
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for IdleTimeout<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,178 @@
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct TlsArgs {
+    /// PEM certificate chain; enables TLS together with --tls-key
+    #[arg(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+}
+
+/* clients that have not finished the TLS handshake by then are dropped */
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/*
+ * Wraps accepted TCP connections, optionally terminating TLS on them.
+ *
+ * The TLS handshake happens in accept(), so call it from the per-client task
+ * and not from the accept loop, otherwise one slow client stalls everyone.
+ * It gives up after the handshake timeout, so a client that never finishes
+ * it does not hold on to its connection slot.
+ */
+#[derive(Clone)]
+pub struct Acceptor {
+    tls: Option<TlsAcceptor>,
+    handshake_timeout: Duration,
+}
+
+impl Default for Acceptor {
+    fn default() -> Self {
+        Acceptor {
+            tls: None,
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+        }
+    }
+}
+
+impl fmt::Debug for Acceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Acceptor")
+            .field("tls", &self.is_tls())
+            .field("handshake_timeout", &self.handshake_timeout)
+            .finish()
+    }
+}
+
+impl Acceptor {
+    pub fn plain() -> Acceptor {
+        Acceptor::default()
+    }
+
+    pub fn tls(cert: impl AsRef<Path>, key: impl AsRef<Path>) -> io::Result<Acceptor> {
+        let (cert, key) = (cert.as_ref(), key.as_ref());
+
+        let certs = CertificateDer::pem_file_iter(cert)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| invalid_data(format!("{}: {e}", cert.display())))?;
+        let key = PrivateKeyDer::from_pem_file(key)
+            .map_err(|e| invalid_data(format!("{}: {e}", key.display())))?;
+
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| invalid_data(e.to_string()))?;
+
+        Ok(Acceptor {
+            tls: Some(TlsAcceptor::from(Arc::new(config))),
+            ..Default::default()
+        })
+    }
+
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Acceptor {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    pub fn from_args(args: &TlsArgs) -> io::Result<Acceptor> {
+        match (&args.tls_cert, &args.tls_key) {
+            (Some(cert), Some(key)) => Acceptor::tls(cert, key),
+            _ => Ok(Acceptor::plain()),
+        }
+    }
+
+    pub fn is_tls(&self) -> bool {
+        self.tls.is_some()
+    }
+
+    pub async fn accept(&self, stream: TcpStream) -> io::Result<Stream> {
+        match &self.tls {
+            Some(tls) => {
+                match tokio::time::timeout(self.handshake_timeout, tls.accept(stream)).await {
+                    Ok(stream) => Ok(Stream::Tls(Box::new(stream?))),
+                    Err(_) => Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "TLS handshake took too long",
+                    )),
+                }
+            }
+            None => Ok(Stream::Plain(stream)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Stream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl Stream {
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Stream::Plain(stream) => stream.peer_addr(),
+            Stream::Tls(stream) => stream.get_ref().0.peer_addr(),
+        }
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use ph_common::limits::{IdleTimeout, LimitArgs, Limits, RateLimiter};
+
+fn ip(addr: &str) -> IpAddr {
+    addr.parse().unwrap()
+}
+
+#[test]
+fn test_connection_caps() {
+    let limits = Limits::new(LimitArgs {
+        max_connections: Some(3),
+        max_connections_per_ip: Some(2),
+        ..Default::default()
+    });
+
+    let first = limits.acquire(ip("10.0.0.1")).unwrap();
+    let _second = limits.acquire(ip("10.0.0.1")).unwrap();
+    assert!(limits.acquire(ip("10.0.0.1")).is_none());
+
+    let _third = limits.acquire(ip("10.0.0.2")).unwrap();
+    assert!(limits.acquire(ip("10.0.0.3")).is_none());
+    assert_eq!(limits.active(), 3);
+    assert_eq!(limits.rejected(), 2);
+
+    /* dropping the guard frees the slot */
+    drop(first);
+    assert_eq!(limits.active(), 2);
+    assert!(limits.acquire(ip("10.0.0.1")).is_some());
+}
+
+#[test]
+fn test_unlimited() {
+    let limits = Limits::new(LimitArgs::default());
+
+    let guards: Vec<_> = (0..1000)
+        .map(|_| limits.acquire(ip("10.0.0.1")).unwrap())
+        .collect();
+    assert_eq!(limits.active(), guards.len());
+    assert_eq!(limits.rejected(), 0);
+    assert_eq!(limits.idle_timeout(), None);
+}
+
+#[test]
+fn test_rate_limiter_burst() {
+    let mut limiter = RateLimiter::new(Some(5));
+
+    for _ in 0..5 {
+        assert!(limiter.try_acquire());
+    }
+    assert!(!limiter.try_acquire());
+    assert!(!limiter.is_full());
+
+    let mut unlimited = RateLimiter::new(None);
+    for _ in 0..1000 {
+        assert!(unlimited.try_acquire());
+    }
+}
+
+#[test]
+fn test_rate_limiter_throttles() {
+    let mut limiter = RateLimiter::new(Some(20));
+
+    let start = Instant::now();
+    for _ in 0..25 {
+        limiter.acquire_blocking();
+    }
+
+    /* 20 from the initial burst, 5 more at 20/s */
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn test_idle_timeout() {
+    let (client, server) = tokio::io::duplex(64);
+    let mut client = client;
+    let mut server = IdleTimeout::new(server, Some(Duration::from_millis(100)));
+
+    let mut buf = [0u8; 5];
+    client.write_all(b"hello").await.unwrap();
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+
+    let err = server.read(&mut buf).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+}
+
+#[tokio::test]
+async fn test_idle_timeout_reset_by_traffic() {
+    let (mut client, server) = tokio::io::duplex(64);
+    let mut server = IdleTimeout::new(server, Some(Duration::from_millis(150)));
+
+    tokio::spawn(async move {
+        for _ in 0..5 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            client.write_all(b"x").await.unwrap();
+        }
+    });
+
+    let mut buf = [0u8; 1];
+    for _ in 0..5 {
+        server.read_exact(&mut buf).await.unwrap();
+    }
+}
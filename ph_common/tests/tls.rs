@@ -0,0 +1,137 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+use ph_common::tls::{Acceptor, TlsArgs};
+
+struct TestCert {
+    cert: PathBuf,
+    key: PathBuf,
+    connector: TlsConnector,
+}
+
+/* self-signed certificate for localhost, written to a per-test temp dir */
+fn generate_cert(name: &str) -> TestCert {
+    let dir = std::env::temp_dir().join(format!("ph_common-{}-{name}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert = dir.join("cert.pem");
+    let key = dir.join("key.pem");
+    std::fs::write(&cert, certified.cert.pem()).unwrap();
+    std::fs::write(&key, certified.key_pair.serialize_pem()).unwrap();
+
+    let mut roots = RootCertStore::empty();
+    roots.add(certified.cert.der().clone()).unwrap();
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    TestCert {
+        cert,
+        key,
+        connector: TlsConnector::from(Arc::new(config)),
+    }
+}
+
+async fn spawn_echo(acceptor: Acceptor) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let hostname = listener.local_addr().unwrap().to_string();
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, _addr) = listener.accept().await.unwrap();
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let mut stream = match acceptor.accept(stream).await {
+                    Ok(stream) => stream,
+                    Err(_) => return,
+                };
+                assert!(stream.peer_addr().is_ok());
+
+                let mut buf = [0u8; 64];
+                while let Ok(n) = stream.read(&mut buf).await {
+                    if n == 0 || stream.write_all(&buf[..n]).await.is_err() {
+                        break;
+                    }
+                }
+                let _ = stream.shutdown().await;
+            });
+        }
+    });
+
+    hostname
+}
+
+#[tokio::test]
+async fn test_tls_roundtrip() {
+    let cert = generate_cert("roundtrip");
+    let acceptor = Acceptor::tls(&cert.cert, &cert.key).unwrap();
+    assert!(acceptor.is_tls());
+    let hostname = spawn_echo(acceptor).await;
+
+    let stream = TcpStream::connect(&hostname).await.unwrap();
+    let domain = ServerName::try_from("localhost").unwrap();
+    let mut stream = cert.connector.connect(domain, stream).await.unwrap();
+
+    stream.write_all(b"hello over tls").await.unwrap();
+    let mut buf = [0u8; 14];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello over tls");
+}
+
+#[tokio::test]
+async fn test_tls_rejects_plaintext_client() {
+    let cert = generate_cert("plaintext");
+    let hostname = spawn_echo(Acceptor::tls(&cert.cert, &cert.key).unwrap()).await;
+
+    let mut stream = TcpStream::connect(&hostname).await.unwrap();
+    stream.write_all(b"hello\n").await.unwrap();
+
+    /* handshake fails, the server drops us without echoing anything back */
+    let mut buf = Vec::new();
+    let _ = stream.read_to_end(&mut buf).await;
+    assert!(!buf.starts_with(b"hello"));
+}
+
+#[tokio::test]
+async fn test_tls_handshake_timeout() {
+    let cert = generate_cert("handshake");
+    let acceptor = Acceptor::tls(&cert.cert, &cert.key)
+        .unwrap()
+        .with_handshake_timeout(Duration::from_millis(200));
+    let hostname = spawn_echo(acceptor).await;
+
+    /* connects but never starts the handshake */
+    let mut stream = TcpStream::connect(&hostname).await.unwrap();
+    let mut buf = Vec::new();
+    let closed = timeout(Duration::from_secs(5), stream.read_to_end(&mut buf)).await;
+    assert!(closed.is_ok(), "Server kept the stalled handshake open");
+    assert!(buf.is_empty());
+}
+
+#[tokio::test]
+async fn test_plain_acceptor() {
+    let hostname = spawn_echo(Acceptor::from_args(&TlsArgs::default()).unwrap()).await;
+
+    let mut stream = TcpStream::connect(&hostname).await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[test]
+fn test_missing_cert() {
+    let args = TlsArgs {
+        tls_cert: Some(PathBuf::from("/nonexistent/cert.pem")),
+        tls_key: Some(PathBuf::from("/nonexistent/key.pem")),
+    };
+    assert!(Acceptor::from_args(&args).is_err());
+}